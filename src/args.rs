@@ -2,6 +2,8 @@ use clap::Parser;
 use reqwest::Url;
 use std::str::FromStr;
 
+use crate::upstream::BalancePolicy;
+
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
 pub struct Args {
@@ -11,8 +13,138 @@ pub struct Args {
     #[arg(short, long, default_value = "8124")]
     pub port: u16,
 
-    #[arg(short, long = "endpoint", value_parser = endpoint_parser)]
-    pub endpoints: Vec<(String, Url)>,
+    #[arg(
+        short,
+        long = "endpoint",
+        value_parser = endpoint_parser,
+        help = "Upstream endpoint as `name=url[,url...]`. Repeat the name or list several urls to add failover upstreams."
+    )]
+    pub endpoints: Vec<(String, Vec<Url>)>,
+
+    #[arg(
+        long,
+        help = "Path to a TOML config file describing chains and their upstreams."
+    )]
+    pub config: Option<String>,
+
+    #[arg(
+        long,
+        value_enum,
+        default_value = "failover",
+        help = "How requests are distributed across the upstreams of a chain."
+    )]
+    pub balance_policy: BalancePolicy,
+
+    #[arg(
+        long,
+        default_value = "10",
+        help = "Seconds between upstream health checks. Setting to zero disables health checks."
+    )]
+    pub health_check_interval: u64,
+
+    #[arg(
+        long,
+        default_value = "5",
+        help = "Number of blocks an upstream may lag behind the best head before being taken out of rotation."
+    )]
+    pub max_head_lag: u64,
+
+    #[arg(
+        long,
+        default_value = "2",
+        help = "Maximum number of retries of requests failing with a transient upstream error. Setting to zero disables retries."
+    )]
+    pub max_retries: u32,
+
+    #[arg(
+        long,
+        default_value = "100",
+        help = "Initial retry backoff in milliseconds."
+    )]
+    pub retry_base_delay_ms: u64,
+
+    #[arg(
+        long,
+        default_value = "2000",
+        help = "Maximum retry backoff in milliseconds."
+    )]
+    pub retry_max_delay_ms: u64,
+
+    #[arg(
+        long,
+        default_value = "10000",
+        help = "Time budget in milliseconds after which a request is not retried anymore."
+    )]
+    pub retry_budget_ms: u64,
+
+    #[arg(
+        long,
+        default_value = "1000",
+        help = "Interval in milliseconds at which the latest, safe and finalized blocks of each chain are polled. Setting to zero disables head tracking."
+    )]
+    pub head_poll_interval_ms: u64,
+
+    #[arg(
+        long,
+        default_value = "0",
+        help = "Milliseconds to wait for concurrent calls to a chain, their uncached requests are sent upstream as a single batch. Setting to zero disables batching across calls."
+    )]
+    pub batch_window_ms: u64,
+
+    #[arg(
+        long,
+        default_value = "100",
+        help = "Size in blocks of the chunks eth_getLogs ranges are cached in, so that overlapping ranges share them. Setting to zero caches each range as a whole."
+    )]
+    pub logs_chunk_size: u64,
+
+    #[arg(
+        long,
+        default_value = "5",
+        help = "Number of failures within the window opening the circuit breaker of an upstream. Setting to zero disables circuit breakers."
+    )]
+    pub circuit_breaker_threshold: usize,
+
+    #[arg(
+        long,
+        default_value = "10000",
+        help = "Window in milliseconds in which circuit breaker failures are counted."
+    )]
+    pub circuit_breaker_window_ms: u64,
+
+    #[arg(
+        long,
+        default_value = "30000",
+        help = "Milliseconds an open circuit breaker waits before letting a probe request through."
+    )]
+    pub circuit_breaker_open_ms: u64,
+
+    #[arg(
+        long,
+        help = "Hedge read-only requests: when the primary upstream is slower than its p95 latency, send them to a second upstream as well."
+    )]
+    pub hedge: bool,
+
+    #[arg(
+        long,
+        default_value = "5000",
+        help = "Timeout in milliseconds to establish a connection to an upstream."
+    )]
+    pub connect_timeout_ms: u64,
+
+    #[arg(
+        long,
+        default_value = "30000",
+        help = "Timeout in milliseconds of an upstream request, unless overridden for the method."
+    )]
+    pub request_timeout_ms: u64,
+
+    #[arg(
+        long = "method-timeout",
+        value_parser = method_timeout_parser,
+        help = "Timeout of a method as `method=ms`, a trailing `*` in the method matches a prefix. Can be repeated."
+    )]
+    pub method_timeouts: Vec<(String, u64)>,
 
     #[arg(short, long, default_value = "100000")]
     pub lru_max_items: usize,
@@ -35,17 +167,29 @@ pub struct Args {
     pub redis_url: Option<String>,
 }
 
-fn endpoint_parser(s: &str) -> Result<(String, Url), String> {
+fn endpoint_parser(s: &str) -> Result<(String, Vec<Url>), String> {
     let part = s.splitn(2, '=').collect::<Vec<_>>();
 
     if part.len() != 2 {
         return Err(format!("Invalid endpoint format: {}", part[0]));
     }
 
-    let url = Url::from_str(part[1]).map_err(|e| e.to_string())?;
+    let urls = part[1]
+        .split(',')
+        .map(|url| Url::from_str(url.trim()).map_err(|e| e.to_string()))
+        .collect::<Result<Vec<_>, _>>()?;
     let name = part[0].to_uppercase();
 
-    Ok((name, url))
+    Ok((name, urls))
+}
+
+fn method_timeout_parser(s: &str) -> Result<(String, u64), String> {
+    let (method, ms) = s
+        .split_once('=')
+        .ok_or_else(|| format!("Invalid method timeout format: {s}"))?;
+    let ms = ms.trim().parse::<u64>().map_err(|e| e.to_string())?;
+
+    Ok((method.trim().to_string(), ms))
 }
 
 fn cache_backend_parser(s: &str) -> Result<String, String> {
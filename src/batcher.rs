@@ -0,0 +1,144 @@
+use std::collections::HashMap;
+use std::mem;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use actix_web::rt;
+use serde_json::json;
+use tokio::sync::oneshot;
+
+use crate::json_rpc::RequestId;
+use crate::metrics::Metrics;
+use crate::upstream::{Responses, UpstreamPool};
+use crate::RpcRequest;
+
+/// Merges the uncached requests of concurrent calls to a chain into a single upstream batch.
+/// The first call of a window waits `window` for others to join, ids are made unique across
+/// the calls and restored on the way back.
+pub struct Batcher {
+    chain: String,
+    window: Duration,
+    upstreams: Arc<UpstreamPool>,
+    client: reqwest::Client,
+    metrics: Metrics,
+    next_id: AtomicU64,
+    queue: Arc<Mutex<Vec<Pending>>>,
+}
+
+struct Pending {
+    requests: Vec<RpcRequest>,
+    /// Original id of each request, by the id it is sent with.
+    ids: HashMap<RequestId, RequestId>,
+    sender: oneshot::Sender<Responses>,
+}
+
+impl Batcher {
+    pub fn new(
+        chain: String,
+        window: Duration,
+        upstreams: Arc<UpstreamPool>,
+        client: reqwest::Client,
+        metrics: Metrics,
+    ) -> Self {
+        Self {
+            chain,
+            window,
+            upstreams,
+            client,
+            metrics,
+            next_id: AtomicU64::new(1),
+            queue: Default::default(),
+        }
+    }
+
+    pub async fn send(&self, mut requests: Vec<RpcRequest>) -> Responses {
+        let mut ids = HashMap::with_capacity(requests.len());
+        for request in requests.iter_mut() {
+            let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+            let id = RequestId::try_from(json!(id)).expect("numeric id");
+            ids.insert(id.clone(), mem::replace(&mut request.id, id));
+        }
+
+        let (sender, receiver) = oneshot::channel();
+        let opens_window = {
+            let mut queue = self.queue.lock().unwrap();
+            queue.push(Pending {
+                requests,
+                ids,
+                sender,
+            });
+            queue.len() == 1
+        };
+
+        if opens_window {
+            rt::spawn(flush(
+                self.chain.clone(),
+                self.window,
+                self.upstreams.clone(),
+                self.client.clone(),
+                self.metrics.clone(),
+                self.queue.clone(),
+            ));
+        }
+
+        // only fails if the flush panicked
+        receiver.await.unwrap_or_default()
+    }
+}
+
+async fn flush(
+    chain: String,
+    window: Duration,
+    upstreams: Arc<UpstreamPool>,
+    client: reqwest::Client,
+    metrics: Metrics,
+    queue: Arc<Mutex<Vec<Pending>>>,
+) {
+    rt::time::sleep(window).await;
+    let pending = mem::take(&mut *queue.lock().unwrap());
+
+    metrics
+        .batch_window_counter
+        .with_label_values(&[&chain])
+        .inc();
+    metrics
+        .batch_window_calls_counter
+        .with_label_values(&[&chain])
+        .inc_by(pending.len() as u64);
+
+    let mut owners = HashMap::new();
+    let mut requests = vec![];
+    let mut outputs = Vec::with_capacity(pending.len());
+    for (index, pending) in pending.into_iter().enumerate() {
+        owners.extend(pending.ids.keys().map(|id| (id.clone(), index)));
+        requests.extend(pending.requests);
+        outputs.push((pending.ids, pending.sender, Responses::default()));
+    }
+
+    let responses = upstreams.send(&client, &metrics, &requests).await;
+
+    for mut value in responses.values {
+        let Some(id) = RequestId::try_from(value["id"].clone()).ok() else {
+            tracing::warn!("batched rpc response has invalid id, response is ignored: {value}");
+            continue;
+        };
+        let Some(index) = owners.get(&id) else {
+            tracing::warn!("batched rpc response has unknown id, response is ignored: {value}");
+            continue;
+        };
+
+        let (ids, _, output) = &mut outputs[*index];
+        let original = ids[&id].clone();
+        value["id"] = json!(original);
+        if responses.unconfirmed.contains(&id) {
+            output.unconfirmed.insert(original);
+        }
+        output.values.push(value);
+    }
+
+    for (_, sender, output) in outputs {
+        // the caller may have gone away meanwhile
+        let _ = sender.send(output);
+    }
+}
@@ -1,5 +1,7 @@
 use lru::LruCache;
+use std::collections::{BTreeMap, HashSet};
 use std::num::NonZeroUsize;
+use std::ops::{Range, RangeInclusive};
 use std::sync::{Arc, Mutex};
 
 use anyhow::Context;
@@ -9,6 +11,7 @@ use super::{CacheBackend, CacheBackendFactory, CacheStatus, CacheValue};
 
 pub struct LruBackendFactory {
     data: Arc<Mutex<LruCache<String, String>>>,
+    tags: Arc<Mutex<BTreeMap<u64, HashSet<String>>>>,
     reorg_ttl: u32,
 }
 
@@ -16,6 +19,7 @@ impl LruBackendFactory {
     pub fn new(cap: usize, reorg_ttl: u32) -> Self {
         Self {
             data: Arc::new(Mutex::new(LruCache::new(NonZeroUsize::new(cap).unwrap()))),
+            tags: Default::default(),
             reorg_ttl,
         }
     }
@@ -25,6 +29,7 @@ impl CacheBackendFactory for LruBackendFactory {
     fn get_instance(&self) -> anyhow::Result<Box<dyn CacheBackend>> {
         Ok(Box::new(LruBackend {
             data: self.data.clone(),
+            tags: self.tags.clone(),
             reorg_ttl: self.reorg_ttl,
         }))
     }
@@ -32,6 +37,7 @@ impl CacheBackendFactory for LruBackendFactory {
 
 pub struct LruBackend {
     data: Arc<Mutex<LruCache<String, String>>>,
+    tags: Arc<Mutex<BTreeMap<u64, HashSet<String>>>>,
     reorg_ttl: u32,
 }
 
@@ -68,4 +74,41 @@ impl CacheBackend for LruBackend {
         let _ = lru_cache.put(key.to_string(), cache_value.to_string()?);
         Ok(())
     }
+
+    fn tag(&mut self, key: &str, blocks: RangeInclusive<u64>) -> anyhow::Result<()> {
+        let mut tags = self.tags.lock().unwrap();
+        for block in blocks {
+            tags.entry(block).or_default().insert(key.to_string());
+        }
+        Ok(())
+    }
+
+    fn delete_tagged(&mut self, block: u64) -> anyhow::Result<usize> {
+        let keys = self.tags.lock().unwrap().remove(&block).unwrap_or_default();
+        let mut lru_cache = self.data.lock().unwrap();
+        Ok(keys
+            .iter()
+            .filter(|key| lru_cache.pop(*key).is_some())
+            .count())
+    }
+
+    fn settle(&mut self, blocks: Range<u64>) -> anyhow::Result<()> {
+        let settled = {
+            let mut tags = self.tags.lock().unwrap();
+            let unsettled = tags.split_off(&blocks.end);
+            std::mem::replace(&mut *tags, unsettled)
+        };
+
+        let mut lru_cache = self.data.lock().unwrap();
+        for key in settled.into_values().flatten().collect::<HashSet<_>>() {
+            let Some(value) = lru_cache.peek(&key) else {
+                continue;
+            };
+            let value = from_str::<CacheValue>(value).context("fail to deserialize cache value")?;
+            if let Some(value) = value.settle(blocks.end) {
+                lru_cache.put(key, value.to_string()?);
+            }
+        }
+        Ok(())
+    }
 }
@@ -1,4 +1,6 @@
-use std::sync::Arc;
+use std::collections::{BTreeMap, HashSet};
+use std::ops::{Range, RangeInclusive};
+use std::sync::{Arc, Mutex};
 
 use anyhow::Context;
 use dashmap::DashMap;
@@ -8,6 +10,7 @@ use super::{CacheBackend, CacheBackendFactory, CacheStatus, CacheValue};
 
 pub struct MemoryBackendFactory {
     data: Arc<DashMap<String, String>>,
+    tags: Arc<Mutex<BTreeMap<u64, HashSet<String>>>>,
     reorg_ttl: u32,
 }
 
@@ -15,6 +18,7 @@ impl MemoryBackendFactory {
     pub fn new(reorg_ttl: u32) -> Self {
         Self {
             data: Arc::new(DashMap::new()),
+            tags: Default::default(),
             reorg_ttl,
         }
     }
@@ -24,6 +28,7 @@ impl CacheBackendFactory for MemoryBackendFactory {
     fn get_instance(&self) -> anyhow::Result<Box<dyn CacheBackend>> {
         Ok(Box::new(MemoryBackend {
             data: self.data.clone(),
+            tags: self.tags.clone(),
             reorg_ttl: self.reorg_ttl,
         }))
     }
@@ -31,6 +36,7 @@ impl CacheBackendFactory for MemoryBackendFactory {
 
 pub struct MemoryBackend {
     data: Arc<DashMap<String, String>>,
+    tags: Arc<Mutex<BTreeMap<u64, HashSet<String>>>>,
     reorg_ttl: u32,
 }
 
@@ -66,4 +72,43 @@ impl CacheBackend for MemoryBackend {
         let _ = self.data.insert(key.to_string(), cache_value.to_string()?);
         Ok(())
     }
+
+    fn tag(&mut self, key: &str, blocks: RangeInclusive<u64>) -> anyhow::Result<()> {
+        let mut tags = self.tags.lock().unwrap();
+        for block in blocks {
+            tags.entry(block).or_default().insert(key.to_string());
+        }
+        Ok(())
+    }
+
+    fn delete_tagged(&mut self, block: u64) -> anyhow::Result<usize> {
+        let keys = self.tags.lock().unwrap().remove(&block).unwrap_or_default();
+        Ok(keys
+            .iter()
+            .filter(|key| self.data.remove(*key).is_some())
+            .count())
+    }
+
+    fn settle(&mut self, blocks: Range<u64>) -> anyhow::Result<()> {
+        let settled = {
+            let mut tags = self.tags.lock().unwrap();
+            let unsettled = tags.split_off(&blocks.end);
+            std::mem::replace(&mut *tags, unsettled)
+        };
+
+        for key in settled.into_values().flatten().collect::<HashSet<_>>() {
+            let Some(value) = self
+                .data
+                .get(&key)
+                .map(|value| from_str::<CacheValue>(&value))
+            else {
+                continue;
+            };
+            let value = value.context("fail to deserialize cache value")?;
+            if let Some(value) = value.settle(blocks.end) {
+                self.data.insert(key, value.to_string()?);
+            }
+        }
+        Ok(())
+    }
 }
@@ -2,6 +2,8 @@ pub mod lru_backend;
 pub mod memory_backend;
 pub mod redis_backend;
 
+use std::ops::{Range, RangeInclusive};
+
 use chrono::Local;
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
@@ -17,6 +19,13 @@ pub struct CacheValue {
     reorg_ttl: u32,
     ttl: u32,
     last_modified: i64,
+    /// Newest block the data derives from, when the head of the chain is tracked.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    block: Option<u64>,
+    /// The block was too deep to be reorganized when the data was written, or settled while the
+    /// data was fresh.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    settled: bool,
 }
 
 impl CacheValue {
@@ -28,10 +37,41 @@ impl CacheValue {
             reorg_ttl,
             ttl,
             last_modified,
+            block: None,
+            settled: false,
         }
     }
 
+    /// Ties the data to `block`: it expires after `reorg_ttl` while the block may still be
+    /// reorganized, and never when it is settled.
+    pub fn with_block(mut self, block: u64, settled: bool) -> Self {
+        self.block = Some(block);
+        self.settled = settled;
+        self
+    }
+
+    pub fn is_settled(&self) -> bool {
+        self.settled
+    }
+
+    /// The same data kept for good, when its block is below `settled_below`, the oldest block
+    /// which may still be reorganized. Only fresh data is kept, data which expired before its
+    /// block settled may be stale or from an orphaned block.
+    pub fn settle(mut self, settled_below: u64) -> Option<Self> {
+        let block = self.block?;
+        if self.settled || block >= settled_below || self.is_expired() {
+            return None;
+        }
+
+        self.settled = true;
+        Some(self)
+    }
+
     pub fn is_expired(&self) -> bool {
+        if self.settled {
+            return false;
+        }
+
         let now = Local::now().timestamp();
         let last_modified = self.last_modified;
         let age: u64 = (now - last_modified) as u64;
@@ -46,6 +86,13 @@ impl CacheValue {
     pub fn update(mut self, expired_value: &Option<Self>, reorg_ttl: u32) -> Self {
         // if a previous entry existed then check if the response has changed
         // else this is a new entry and nothing to do
+        // data tied to a block expires by depth, the reorg ttl is kept short
+        if self.block.is_some() {
+            self.last_modified = Local::now().timestamp();
+            self.reorg_ttl = reorg_ttl;
+            return self;
+        }
+
         if let Some(expired_value) = expired_value {
             let is_new = expired_value.data != self.data;
             self.last_modified = Local::now().timestamp();
@@ -74,10 +121,6 @@ impl CacheValue {
     pub fn to_string(&self) -> anyhow::Result<String> {
         Ok(serde_json::to_string(&self)?)
     }
-
-    pub fn from_str(value: &str) -> anyhow::Result<Self> {
-        Ok(serde_json::from_str(value)?)
-    }
 }
 
 pub trait CacheBackendFactory: Send + Sync {
@@ -93,4 +136,60 @@ pub trait CacheBackend {
         cache_value: CacheValue,
         expired_value: &Option<CacheValue>,
     ) -> anyhow::Result<()>;
+
+    /// Tags the entry at `key` as derived from the `blocks`, to drop it if any of them is
+    /// reorganized.
+    fn tag(&mut self, key: &str, blocks: RangeInclusive<u64>) -> anyhow::Result<()>;
+    /// Deletes the entries tagged with `block`, returns how many were deleted.
+    fn delete_tagged(&mut self, block: u64) -> anyhow::Result<usize>;
+    /// The `blocks` became too deep to be reorganized: their entries still fresh are kept for good
+    /// (see [`CacheValue::settle`]) and their tags forgotten, along with those of any older block.
+    fn settle(&mut self, blocks: Range<u64>) -> anyhow::Result<()>;
+}
+
+#[cfg(test)]
+mod test {
+    use serde_json::{from_value, json};
+
+    use super::*;
+
+    fn old_value() -> CacheValue {
+        from_value(json!({"data": "0x1", "reorg_ttl": 12, "ttl": 86400, "last_modified": 0}))
+            .unwrap()
+    }
+
+    #[test]
+    fn test_expired_by_depth() {
+        assert!(old_value().is_expired());
+        assert!(old_value().with_block(99, false).is_expired());
+        assert!(!old_value().with_block(99, true).is_expired());
+    }
+
+    #[test]
+    fn test_settle() {
+        let fresh = || CacheValue::new(json!("0x1"), 12, 86400).with_block(99, false);
+
+        let value = fresh().settle(100).unwrap();
+        assert!(value.is_settled());
+
+        // the block may still be reorganized
+        assert!(fresh().settle(99).is_none());
+
+        // expired data doesn't come back when its block settles
+        assert!(old_value().with_block(99, false).settle(100).is_none());
+        assert!(old_value().settle(100).is_none());
+    }
+
+    #[test]
+    fn test_no_backoff_with_block() {
+        let expired = Some(old_value());
+
+        let value = CacheValue::new(json!("0x1"), 12, 86400).update(&expired, 12);
+        assert_eq!(value.effective_ttl(), 24);
+
+        let value = CacheValue::new(json!("0x1"), 12, 86400)
+            .with_block(99, false)
+            .update(&expired, 12);
+        assert_eq!(value.effective_ttl(), 12);
+    }
 }
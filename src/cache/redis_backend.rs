@@ -1,9 +1,14 @@
+use std::ops::{Range, RangeInclusive};
+
 use anyhow::Context;
 use redis::Commands;
 use serde_json::from_str;
 
 use super::{CacheBackend, CacheBackendFactory, CacheStatus, CacheValue};
 
+/// Seconds a block tag is kept, well beyond the depth of any reorg.
+const TAG_TTL: i64 = 3600;
+
 pub struct RedisBackendFactory {
     chain_id: u64,
     client: r2d2::Pool<redis::Client>,
@@ -36,6 +41,12 @@ pub struct RedisBackend {
     reorg_ttl: u32,
 }
 
+impl RedisBackend {
+    fn tag_key(&self, block: u64) -> String {
+        format!("{}:reorg-tag:{block}", self.chain_id)
+    }
+}
+
 impl CacheBackend for RedisBackend {
     fn get_reorg_ttl(&self) -> u32 {
         self.reorg_ttl
@@ -67,10 +78,70 @@ impl CacheBackend for RedisBackend {
         expired_value: &Option<CacheValue>,
     ) -> anyhow::Result<()> {
         let cache_value = cache_value.update(expired_value, self.reorg_ttl);
+        if cache_value.is_settled() {
+            let _ = self.conn.set::<_, _, String>(key, cache_value.to_string()?);
+            return Ok(());
+        }
+
         let redis_ttl = cache_value.effective_ttl() * 2;
         let _ = self
             .conn
             .set_ex::<_, _, String>(key, cache_value.to_string()?, redis_ttl.into());
         Ok(())
     }
+
+    fn tag(&mut self, key: &str, blocks: RangeInclusive<u64>) -> anyhow::Result<()> {
+        // a single round trip, however many blocks
+        let mut pipe = redis::pipe();
+        for block in blocks {
+            let tag_key = self.tag_key(block);
+            pipe.sadd(&tag_key, key)
+                .ignore()
+                .expire(&tag_key, TAG_TTL)
+                .ignore();
+        }
+        pipe.query::<()>(&mut *self.conn)?;
+        Ok(())
+    }
+
+    fn delete_tagged(&mut self, block: u64) -> anyhow::Result<usize> {
+        let tag_key = self.tag_key(block);
+        let keys: Vec<String> = self.conn.smembers(&tag_key)?;
+
+        let mut deleted = 0;
+        if !keys.is_empty() {
+            deleted = self.conn.del(&keys)?;
+        }
+        self.conn.del::<_, ()>(&tag_key)?;
+
+        Ok(deleted)
+    }
+
+    fn settle(&mut self, blocks: Range<u64>) -> anyhow::Result<()> {
+        // tags of older blocks expire by themselves
+        for block in blocks.clone() {
+            let tag_key = self.tag_key(block);
+            let keys: Vec<String> = self.conn.smembers(&tag_key)?;
+
+            let mut pipe = redis::pipe();
+            if !keys.is_empty() {
+                let values: Vec<Option<String>> =
+                    redis::cmd("MGET").arg(&keys).query(&mut *self.conn)?;
+
+                for (key, value) in keys.iter().zip(values) {
+                    let Some(value) = value else {
+                        continue;
+                    };
+                    let value = from_str::<CacheValue>(&value)
+                        .context("fail to deserialize cache value")?;
+                    // without an expiry
+                    if let Some(value) = value.settle(blocks.end) {
+                        pipe.set(key, value.to_string()?).ignore();
+                    }
+                }
+            }
+            pipe.del(&tag_key).ignore().query::<()>(&mut *self.conn)?;
+        }
+        Ok(())
+    }
 }
@@ -0,0 +1,121 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use serde_json::Value;
+use tokio::sync::oneshot;
+
+/// Single-flight deduplication of concurrent cache misses. The first request missing a cache key
+/// leads the flight and goes upstream, requests missing the same key meanwhile follow it and get
+/// a copy of its response.
+#[derive(Default)]
+pub struct Coalescer {
+    inflight: Mutex<HashMap<String, Vec<oneshot::Sender<Value>>>>,
+}
+
+pub enum Flight<'a> {
+    Leader(Leader<'a>),
+    /// Receives the response of the leader, or an error when the leader went away without one.
+    Follower(oneshot::Receiver<Value>),
+}
+
+impl Coalescer {
+    pub fn join(&self, key: &str) -> Flight<'_> {
+        let mut inflight = self.inflight.lock().unwrap();
+        match inflight.get_mut(key) {
+            Some(followers) => {
+                let (sender, receiver) = oneshot::channel();
+                followers.push(sender);
+                Flight::Follower(receiver)
+            }
+            None => {
+                inflight.insert(key.to_string(), vec![]);
+                Flight::Leader(Leader {
+                    coalescer: self,
+                    key: Some(key.to_string()),
+                })
+            }
+        }
+    }
+}
+
+/// Ends the flight when completed or dropped, so that later misses of the key lead a new one.
+pub struct Leader<'a> {
+    coalescer: &'a Coalescer,
+    key: Option<String>,
+}
+
+impl Leader<'_> {
+    /// Shares the upstream response with the followers.
+    pub fn complete(mut self, response: &Value) {
+        for follower in self.land() {
+            let _ = follower.send(response.clone());
+        }
+    }
+
+    fn land(&mut self) -> Vec<oneshot::Sender<Value>> {
+        match self.key.take() {
+            Some(key) => self
+                .coalescer
+                .inflight
+                .lock()
+                .unwrap()
+                .remove(&key)
+                .unwrap_or_default(),
+            None => vec![],
+        }
+    }
+}
+
+impl Drop for Leader<'_> {
+    fn drop(&mut self) {
+        // dropping the senders lets the followers know they are on their own
+        self.land();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use serde_json::json;
+
+    use super::*;
+
+    #[actix_web::test]
+    async fn test_coalesce() {
+        let coalescer = Coalescer::default();
+
+        let Flight::Leader(leader) = coalescer.join("eth_chainId:") else {
+            panic!("first miss should lead");
+        };
+        let Flight::Follower(first) = coalescer.join("eth_chainId:") else {
+            panic!("second miss should follow");
+        };
+        let Flight::Follower(second) = coalescer.join("eth_chainId:") else {
+            panic!("third miss should follow");
+        };
+        assert!(matches!(
+            coalescer.join("eth_blockNumber:"),
+            Flight::Leader(_)
+        ));
+
+        leader.complete(&json!({"id": 1, "result": "0x1"}));
+        assert_eq!(first.await.unwrap(), json!({"id": 1, "result": "0x1"}));
+        assert_eq!(second.await.unwrap(), json!({"id": 1, "result": "0x1"}));
+
+        // the flight is over, the next miss leads again
+        assert!(matches!(coalescer.join("eth_chainId:"), Flight::Leader(_)));
+    }
+
+    #[actix_web::test]
+    async fn test_leader_dropped() {
+        let coalescer = Coalescer::default();
+
+        let leader = coalescer.join("eth_chainId:");
+        let Flight::Follower(follower) = coalescer.join("eth_chainId:") else {
+            panic!("second miss should follow");
+        };
+
+        drop(leader);
+        assert!(follower.await.is_err());
+        assert!(matches!(coalescer.join("eth_chainId:"), Flight::Leader(_)));
+    }
+}
@@ -0,0 +1,332 @@
+use std::collections::BTreeMap;
+use std::str::FromStr;
+
+use anyhow::Context;
+use reqwest::Url;
+use serde::{Deserialize, Deserializer};
+
+use crate::args::Args;
+use crate::upstream::{
+    AuthConfig, BalancePolicy, HedgeConfig, QuorumConfig, RateLimitConfig, RouteConfig, Secret,
+    DEFAULT_GROUP,
+};
+
+/// Optional file based configuration (`--config`). Chains declared with `--endpoint` on the
+/// command line are merged into it, so both ways of configuring upstreams can be combined.
+#[derive(Deserialize, Default, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    #[serde(default)]
+    pub chains: BTreeMap<String, ChainConfig>,
+}
+
+#[derive(Deserialize, Default, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct ChainConfig {
+    /// Upstreams in failover order.
+    #[serde(default)]
+    pub upstreams: Vec<UpstreamConfig>,
+
+    /// Overrides `--balance-policy` for this chain.
+    #[serde(default)]
+    pub balance: Option<BalancePolicy>,
+
+    /// Routing rules sending some methods to a specific upstream group, the first matching
+    /// rule wins. Other methods go to the `default` group.
+    #[serde(default)]
+    pub routes: Vec<RouteConfig>,
+
+    /// Timeouts in milliseconds by method name, on top of the `--method-timeout` ones.
+    #[serde(default)]
+    pub method_timeouts: BTreeMap<String, u64>,
+
+    /// Enables hedging for this chain, `--hedge` enables it with the default settings.
+    #[serde(default)]
+    pub hedge: Option<HedgeConfig>,
+
+    /// Only trust the answers to some methods when several upstreams agree on them.
+    #[serde(default)]
+    pub quorum: Option<QuorumConfig>,
+}
+
+#[derive(Deserialize, Clone, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct UpstreamConfig {
+    #[serde(deserialize_with = "deserialize_url")]
+    pub url: Url,
+
+    /// Name used in logs, defaults to the host of the url.
+    #[serde(default)]
+    pub name: Option<String>,
+
+    /// Relative share of the traffic with the `weighted` balance policy.
+    #[serde(default = "default_weight")]
+    pub weight: u32,
+
+    /// Group the upstream belongs to, see [`RouteConfig`].
+    #[serde(default = "default_group")]
+    pub group: String,
+
+    /// Set to `false` for upstreams rejecting JSON-RPC batches, requests are then sent one by
+    /// one in parallel.
+    #[serde(default = "default_batch")]
+    pub batch: bool,
+
+    /// Batches larger than this are split in concurrent chunks.
+    #[serde(default)]
+    pub max_batch_size: Option<usize>,
+
+    /// Extra headers sent with every request.
+    #[serde(default)]
+    pub headers: BTreeMap<String, Secret>,
+
+    #[serde(default)]
+    pub auth: Option<AuthConfig>,
+
+    /// Outbound quota, requests over it go to another upstream or queue for a while.
+    #[serde(default)]
+    pub rate_limit: Option<RateLimitConfig>,
+}
+
+impl UpstreamConfig {
+    pub fn new(url: Url) -> Self {
+        Self {
+            url,
+            name: None,
+            weight: default_weight(),
+            group: default_group(),
+            batch: default_batch(),
+            max_batch_size: None,
+            headers: BTreeMap::new(),
+            auth: None,
+            rate_limit: None,
+        }
+    }
+}
+
+fn default_weight() -> u32 {
+    1
+}
+
+fn default_group() -> String {
+    DEFAULT_GROUP.to_string()
+}
+
+fn default_batch() -> bool {
+    true
+}
+
+impl Config {
+    pub fn load(args: &Args) -> anyhow::Result<Self> {
+        let mut config = match &args.config {
+            Some(path) => {
+                let content = std::fs::read_to_string(path)
+                    .with_context(|| format!("fail to read config file {path}"))?;
+                Self::from_str(&content).with_context(|| format!("invalid config file {path}"))?
+            }
+            None => Self::default(),
+        };
+
+        for (name, urls) in args.endpoints.iter() {
+            let chain = config.chains.entry(name.clone()).or_default();
+            chain
+                .upstreams
+                .extend(urls.iter().cloned().map(UpstreamConfig::new));
+        }
+
+        // per chain timeouts take precedence over the command line ones
+        for chain in config.chains.values_mut() {
+            for (method, ms) in args.method_timeouts.iter() {
+                chain.method_timeouts.entry(method.clone()).or_insert(*ms);
+            }
+        }
+
+        for (name, chain) in config.chains.iter() {
+            if chain.upstreams.is_empty() {
+                anyhow::bail!("chain `{name}` has no upstream configured");
+            }
+
+            if chain.upstreams.iter().any(|upstream| upstream.weight == 0) {
+                anyhow::bail!("chain `{name}` has an upstream with zero weight");
+            }
+
+            if chain
+                .upstreams
+                .iter()
+                .any(|upstream| upstream.max_batch_size == Some(0))
+            {
+                anyhow::bail!("chain `{name}` has an upstream with zero max batch size");
+            }
+        }
+
+        Ok(config)
+    }
+}
+
+impl FromStr for Config {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let config: Config = toml::from_str(s)?;
+
+        // chain names are case insensitive, the same way as `--endpoint`
+        let chains = config
+            .chains
+            .into_iter()
+            .map(|(name, chain)| (name.to_uppercase(), chain))
+            .collect();
+
+        Ok(Self { chains })
+    }
+}
+
+fn deserialize_url<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Url, D::Error> {
+    let s = String::deserialize(deserializer)?;
+    Url::from_str(&s).map_err(serde::de::Error::custom)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let config = Config::from_str(
+            r#"
+            [chains.eth]
+            balance = "weighted"
+            upstreams = [
+                { url = "https://rpc.ankr.com/eth", max_batch_size = 100 },
+                { url = "https://eth.llamarpc.com", name = "llama", weight = 3, batch = false },
+            ]
+            "#,
+        )
+        .unwrap();
+
+        let chain = config.chains.get("ETH").unwrap();
+        assert_eq!(chain.balance, Some(BalancePolicy::Weighted));
+        assert_eq!(chain.upstreams.len(), 2);
+        assert_eq!(chain.upstreams[0].url.as_str(), "https://rpc.ankr.com/eth");
+        assert_eq!(chain.upstreams[0].name, None);
+        assert_eq!(chain.upstreams[0].weight, 1);
+        assert!(chain.upstreams[0].batch);
+        assert_eq!(chain.upstreams[0].max_batch_size, Some(100));
+        assert_eq!(chain.upstreams[1].name.as_deref(), Some("llama"));
+        assert_eq!(chain.upstreams[1].weight, 3);
+        assert!(!chain.upstreams[1].batch);
+        assert_eq!(chain.upstreams[1].max_batch_size, None);
+    }
+
+    #[test]
+    fn test_parse_routes() {
+        let config = Config::from_str(
+            r#"
+            [chains.eth]
+            upstreams = [
+                { url = "https://rpc.ankr.com/eth" },
+                { url = "https://archive.example.com", group = "archive" },
+            ]
+            routes = [
+                { methods = ["debug_*", "trace_*", "eth_getProof"], group = "archive" },
+                { methods = ["eth_call"], group = "archive", min_block_depth = 128 },
+            ]
+            "#,
+        )
+        .unwrap();
+
+        let chain = config.chains.get("ETH").unwrap();
+        assert_eq!(chain.upstreams[0].group, "default");
+        assert_eq!(chain.upstreams[1].group, "archive");
+        assert_eq!(chain.routes.len(), 2);
+        assert_eq!(
+            chain.routes[0].methods,
+            vec!["debug_*", "trace_*", "eth_getProof"]
+        );
+        assert_eq!(chain.routes[0].min_block_depth, None);
+        assert_eq!(chain.routes[1].min_block_depth, Some(128));
+    }
+
+    #[test]
+    fn test_parse_method_timeouts() {
+        let config = Config::from_str(
+            r#"
+            [chains.eth]
+            upstreams = [{ url = "https://rpc.ankr.com/eth" }]
+            method_timeouts = { eth_blockNumber = 2000, "debug_*" = 120000 }
+            "#,
+        )
+        .unwrap();
+
+        let chain = config.chains.get("ETH").unwrap();
+        assert_eq!(chain.method_timeouts["eth_blockNumber"], 2000);
+        assert_eq!(chain.method_timeouts["debug_*"], 120000);
+    }
+
+    #[test]
+    fn test_parse_auth() {
+        let config = Config::from_str(
+            r#"
+            [chains.eth]
+            upstreams = [
+                { url = "https://rpc.example.com", headers = { x-client = "cached-eth-rpc" }, auth = { type = "bearer", token = "secret" } },
+                { url = "https://key.example.com", auth = { type = "api-key", key = "secret" } },
+                { url = "http://127.0.0.1:8551", auth = { type = "jwt", secret_file = "/tmp/jwt.hex" } },
+            ]
+            "#,
+        )
+        .unwrap();
+
+        let chain = config.chains.get("ETH").unwrap();
+        assert_eq!(
+            chain.upstreams[0].headers["x-client"].expose(),
+            "cached-eth-rpc"
+        );
+        assert!(matches!(
+            &chain.upstreams[0].auth,
+            Some(AuthConfig::Bearer { token }) if token.expose() == "secret"
+        ));
+        assert!(matches!(
+            &chain.upstreams[1].auth,
+            Some(AuthConfig::ApiKey { header, .. }) if header == "x-api-key"
+        ));
+        assert!(matches!(
+            &chain.upstreams[2].auth,
+            Some(AuthConfig::Jwt {
+                secret: None,
+                secret_file: Some(_)
+            })
+        ));
+        assert!(!format!("{config:?}").contains("secret\""));
+    }
+
+    #[test]
+    fn test_parse_quorum() {
+        let config = Config::from_str(
+            r#"
+            [chains.eth]
+            upstreams = [{ url = "https://rpc.ankr.com/eth" }]
+            quorum = { min_agree = 3, on_disagreement = "majority" }
+            "#,
+        )
+        .unwrap();
+
+        let quorum = config.chains["ETH"].quorum.as_ref().unwrap();
+        assert_eq!(quorum.methods.len(), 3);
+        assert!(quorum.matches("eth_getTransactionReceipt"));
+        assert_eq!(quorum.size, 3);
+        assert_eq!(quorum.min_agree, 3);
+    }
+
+    #[test]
+    fn test_invalid_url() {
+        let err = Config::from_str(
+            r#"
+            [chains.eth]
+            upstreams = [{ url = "not a url" }]
+            "#,
+        )
+        .unwrap_err();
+
+        assert!(err.to_string().contains("relative URL without a base"));
+    }
+}
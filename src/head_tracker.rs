@@ -0,0 +1,814 @@
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
+
+use anyhow::Context;
+use serde_json::{json, Value};
+
+use crate::cache::{CacheBackend, CacheBackendFactory, CacheStatus};
+use crate::json_rpc::RequestId;
+use crate::metrics::Metrics;
+use crate::rpc_cache_handler::{common, EthBlockNumber, EthGetBlockByNumber, RpcCacheHandler};
+use crate::upstream::{Upstream, UpstreamPool};
+use crate::RpcRequest;
+
+/// Number of recent canonical block hashes kept below the head.
+const RECENT_BLOCKS: u64 = 128;
+
+/// Blocks fetched at once when filling a gap or walking back a reorg.
+const BACKFILL_CHUNK: u64 = 16;
+
+/// Polls missed in a row before the head is out of date, and the upstreams are asked instead.
+const STALE_AFTER_POLLS: u32 = 3;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BlockRef {
+    pub number: u64,
+    pub hash: String,
+    pub parent_hash: String,
+}
+
+impl BlockRef {
+    fn from_block(block: &Value) -> anyhow::Result<Self> {
+        let number = block["number"].as_str().context("block without number")?;
+        let number = u64::from_str_radix(number.trim_start_matches("0x"), 16)
+            .context("invalid block number")?;
+        let hash = block["hash"].as_str().context("block without hash")?;
+        let parent_hash = block["parentHash"]
+            .as_str()
+            .context("block without parent hash")?;
+
+        Ok(Self {
+            number,
+            hash: hash.to_lowercase(),
+            parent_hash: parent_hash.to_lowercase(),
+        })
+    }
+}
+
+/// Follows the head of a chain: the latest, safe and finalized blocks, and the hashes of the
+/// recent canonical blocks below the highest latest one.
+#[derive(Default)]
+pub struct HeadTracker {
+    state: RwLock<State>,
+    /// Age after which the head isn't trusted anymore, when polling fails. Never for a tracker
+    /// which isn't polled, e.g. in tests.
+    max_age: Option<Duration>,
+}
+
+#[derive(Default)]
+struct State {
+    /// Highest latest block across the upstreams, the recent blocks are linked to it.
+    latest: Option<BlockRef>,
+    heads: Option<Heads>,
+    /// Pending block of the upstream with the highest head, without transactions.
+    pending: Option<Value>,
+    recent: BTreeMap<u64, String>,
+    polled: Option<Instant>,
+}
+
+/// Numbers of the blocks the `latest`, `safe` and `finalized` tags point at: the lowest among
+/// the healthy upstreams, so that any of them can serve a request pinned to these blocks.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+struct Heads {
+    latest: u64,
+    safe: Option<u64>,
+    finalized: Option<u64>,
+}
+
+impl Heads {
+    /// The lowest heads of the healthy upstreams, or of all of them when none is healthy.
+    /// Upstreams without a safe or finalized block don't count for that tag.
+    fn pin(polls: &[(bool, Heads)]) -> Option<Self> {
+        let healthy = polls.iter().any(|(healthy, _)| *healthy);
+        let polls = polls
+            .iter()
+            .filter(|(is_healthy, _)| *is_healthy || !healthy)
+            .map(|(_, heads)| heads);
+
+        polls.fold(None, |pinned: Option<Heads>, heads| match pinned {
+            None => Some(*heads),
+            Some(pinned) => Some(Heads {
+                latest: pinned.latest.min(heads.latest),
+                safe: lowest(pinned.safe, heads.safe),
+                finalized: lowest(pinned.finalized, heads.finalized),
+            }),
+        })
+    }
+}
+
+fn lowest(a: Option<u64>, b: Option<u64>) -> Option<u64> {
+    match (a, b) {
+        (Some(a), Some(b)) => Some(a.min(b)),
+        (a, b) => a.or(b),
+    }
+}
+
+impl HeadTracker {
+    pub fn new(poll_interval: Duration) -> Self {
+        Self {
+            state: Default::default(),
+            max_age: Some(poll_interval * STALE_AFTER_POLLS),
+        }
+    }
+
+    /// Number of the block a `latest`, `safe` or `finalized` tag currently points at, unless the
+    /// head is out of date.
+    pub fn resolve(&self, tag: &str) -> Option<u64> {
+        let state = self.state.read().unwrap();
+        if !self.is_fresh(&state) {
+            return None;
+        }
+
+        let heads = state.heads.as_ref()?;
+        match tag {
+            "latest" => Some(heads.latest),
+            "safe" => heads.safe,
+            "finalized" => heads.finalized,
+            _ => None,
+        }
+    }
+
+    /// The pending block without transactions, unless the head is out of date.
+    pub fn pending(&self) -> Option<Value> {
+        let state = self.state.read().unwrap();
+        match self.is_fresh(&state) {
+            true => state.pending.clone(),
+            false => None,
+        }
+    }
+
+    fn is_fresh(&self, state: &State) -> bool {
+        match (self.max_age, state.polled) {
+            (Some(max_age), Some(polled)) => polled.elapsed() <= max_age,
+            (Some(_), None) => false,
+            (None, _) => true,
+        }
+    }
+
+    /// Hash of the canonical block at `number`, if recent enough to be known.
+    pub fn canonical_hash(&self, number: u64) -> Option<String> {
+        self.state.read().unwrap().recent.get(&number).cloned()
+    }
+
+    /// Number of the canonical block with the given lowercase hash, if recent enough to be known.
+    pub fn canonical_number(&self, hash: &str) -> Option<u64> {
+        let state = self.state.read().unwrap();
+        let mut recent = state.recent.iter();
+        recent.find_map(|(number, canonical)| (canonical == hash).then_some(*number))
+    }
+
+    /// Oldest block which could still be reorganized: above the finalized one, or below the
+    /// recent blocks on chains without finality.
+    pub fn oldest_unsettled(&self) -> Option<u64> {
+        let state = self.state.read().unwrap();
+        let latest = state.latest.as_ref()?.number;
+        match state.heads.and_then(|heads| heads.finalized) {
+            Some(finalized) => Some(finalized + 1),
+            None => Some(latest.saturating_sub(RECENT_BLOCKS - 1)),
+        }
+    }
+
+    /// Part of the inclusive block range which could still be reorganized, within the recent
+    /// blocks where a reorganization is noticed.
+    pub fn unsettled(&self, (from, to): (u64, u64)) -> Option<(u64, u64)> {
+        let latest = self.state.read().unwrap().latest.as_ref()?.number;
+        let oldest = self.oldest_unsettled()?;
+        let from = from
+            .max(oldest)
+            .max(latest.saturating_sub(RECENT_BLOCKS - 1));
+        // results may be a little ahead of the last poll
+        let to = to.min(latest.saturating_add(RECENT_BLOCKS));
+        (from <= to).then_some((from, to))
+    }
+
+    /// Numbers of the blocks to fetch before `blocks` can be linked to the known recent
+    /// blocks, either to fill a gap or because the chain reorganized.
+    fn missing(&self, blocks: &[BlockRef]) -> Option<(u64, u64)> {
+        let state = self.state.read().unwrap();
+        let lowest = blocks.iter().min_by_key(|block| block.number)?;
+        let top = blocks.iter().map(|block| block.number).max()?;
+
+        let (&first, _) = state.recent.first_key_value()?;
+        let (&last, _) = state.recent.last_key_value()?;
+
+        let parent = lowest.number.checked_sub(1)?;
+        if parent < first || top - parent >= RECENT_BLOCKS {
+            return None;
+        }
+        if state.recent.get(&parent) == Some(&lowest.parent_hash) {
+            return None;
+        }
+
+        // walk back a reorg, or fill the gap above the last known block
+        let from = match parent > last {
+            true => (last + 1).max(parent + 1 - BACKFILL_CHUNK),
+            false => first.max(parent + 1 - BACKFILL_CHUNK.min(parent + 1)),
+        };
+        Some((from, parent))
+    }
+
+    /// Records the heads the tags point at, the highest latest block and the canonical blocks
+    /// below it, returns the numbers of the known blocks whose hash changed.
+    fn apply(&self, heads: Heads, latest: BlockRef, blocks: Vec<BlockRef>) -> Vec<u64> {
+        let mut state = self.state.write().unwrap();
+        state.polled = Some(Instant::now());
+        state.heads = Some(heads);
+
+        // a lower head may only come from upstreams lagging behind the one that failed, the
+        // blocks above it are still canonical
+        if let Some(known) = &state.latest {
+            if latest.number < known.number {
+                return vec![];
+            }
+        }
+
+        let mut reorged = vec![];
+        for block in blocks.iter().chain([&latest]) {
+            if let Some(previous) = state.recent.insert(block.number, block.hash.clone()) {
+                if previous != block.hash {
+                    reorged.push(block.number);
+                }
+            }
+        }
+
+        let oldest = latest.number.saturating_sub(RECENT_BLOCKS - 1);
+        state.recent = state.recent.split_off(&oldest);
+
+        state.latest = Some(latest);
+
+        reorged.sort_unstable();
+        reorged
+    }
+}
+
+/// Polls the upstreams of the pool for their head blocks every `interval`, and drops the cached entries derived
+/// from reorganized blocks.
+pub fn spawn(
+    tracker: Arc<HeadTracker>,
+    pool: Arc<UpstreamPool>,
+    cache_factory: Arc<dyn CacheBackendFactory>,
+    client: reqwest::Client,
+    metrics: Metrics,
+    interval: Duration,
+) {
+    actix_web::rt::spawn(async move {
+        let mut ticker = actix_web::rt::time::interval(interval);
+        let mut settled_below = None;
+
+        loop {
+            ticker.tick().await;
+
+            let cache_factory = &*cache_factory;
+            let result = poll(&tracker, &pool, cache_factory, &client, &metrics, interval).await;
+            let result = result.and_then(|_| settle(&tracker, cache_factory, &mut settled_below));
+            if let Err(err) = result {
+                tracing::warn!(chain = pool.chain(), "fail to poll chain head: {err:#}");
+            }
+        }
+    });
+}
+
+async fn poll(
+    tracker: &HeadTracker,
+    pool: &UpstreamPool,
+    cache_factory: &dyn CacheBackendFactory,
+    client: &reqwest::Client,
+    metrics: &Metrics,
+    timeout: Duration,
+) -> anyhow::Result<()> {
+    let tags = ["latest", "safe", "finalized"];
+
+    // each upstream is asked apart, a quorum would fail whenever two of them are a block apart,
+    // and the polls must neither use up the rate limits nor trip the circuit breakers
+    let polls = futures::future::join_all(
+        pool.upstreams()
+            .iter()
+            .map(|upstream| get_blocks(upstream, client, timeout, tags.map(|tag| json!(tag)))),
+    )
+    .await;
+
+    let mut answers = vec![];
+    for (upstream, result) in pool.upstreams().iter().zip(polls) {
+        match result {
+            Ok(heads) => match &heads[0] {
+                Some(_) => answers.push((upstream, heads)),
+                None => tracing::debug!(
+                    chain = pool.chain(),
+                    upstream = upstream.name,
+                    "no latest block"
+                ),
+            },
+            Err(err) => tracing::debug!(
+                chain = pool.chain(),
+                upstream = upstream.name,
+                "fail to poll head: {err:#}"
+            ),
+        }
+    }
+
+    let number = |head: &Option<(BlockRef, Value)>| head.as_ref().map(|(block, _)| block.number);
+    let polled = answers
+        .iter()
+        .filter_map(|(upstream, heads)| {
+            let heads = Heads {
+                latest: number(&heads[0])?,
+                safe: number(&heads[1]),
+                finalized: number(&heads[2]),
+            };
+            Some((upstream.is_healthy(), heads))
+        })
+        .collect::<Vec<_>>();
+    let pinned = Heads::pin(&polled).context("no upstream answered")?;
+
+    // the chain is followed along the highest head
+    let (upstream, heads) = answers
+        .into_iter()
+        .max_by_key(|(_, heads)| number(&heads[0]))
+        .context("no upstream answered")?;
+    let (latest, _) = heads[0].clone().context("no latest block")?;
+
+    let mut blocks = vec![latest.clone()];
+    while let Some((from, to)) = tracker.missing(&blocks) {
+        let numbers = (from..=to).map(|number| json!(format!("0x{number:x}")));
+        let fetched = get_blocks(upstream, client, timeout, numbers).await?;
+        if fetched.iter().any(Option::is_none) {
+            break;
+        }
+        blocks.extend(fetched.into_iter().flatten().map(|(block, _)| block));
+    }
+
+    // pending blocks have no hash, they are kept apart from the chain
+    let pending = get_pending(upstream, client, timeout).await;
+    tracker.state.write().unwrap().pending = pending;
+
+    let reorged = tracker.apply(pinned, latest, blocks);
+
+    for tag in tags {
+        if let Some(number) = tracker.resolve(tag) {
+            metrics
+                .head_block_gauge
+                .with_label_values(&[pool.chain(), tag])
+                .set(number as i64);
+        }
+    }
+
+    let mut cache_backend = cache_factory
+        .get_instance()
+        .context("fail to get cache backend")?;
+
+    if !reorged.is_empty() {
+        let mut deleted = 0;
+        for block in &reorged {
+            deleted += cache_backend.delete_tagged(*block)?;
+        }
+
+        metrics
+            .reorg_counter
+            .with_label_values(&[pool.chain()])
+            .inc();
+        metrics
+            .reorg_invalidated_counter
+            .with_label_values(&[pool.chain()])
+            .inc_by(deleted as u64);
+        tracing::warn!(
+            chain = pool.chain(),
+            ?reorged,
+            deleted,
+            "chain reorganized, dropped the cached entries of the orphaned blocks"
+        );
+    }
+
+    cache_block_number(tracker, &mut *cache_backend)?;
+    cache_heads(tracker, &mut *cache_backend, heads.into_iter().flatten())
+}
+
+/// Caches the latest block number as `eth_blockNumber` would, so that instances sharing the cache
+/// answer the same number. A lower number than the cached one, from an instance whose upstreams
+/// lag, doesn't move it back.
+fn cache_block_number(
+    tracker: &HeadTracker,
+    cache_backend: &mut dyn CacheBackend,
+) -> anyhow::Result<()> {
+    let Some(latest) = tracker.resolve("latest") else {
+        return Ok(());
+    };
+
+    let handler = EthBlockNumber::default();
+    let params_key = handler
+        .extract_cache_key(&json!([]))?
+        .context("block number without cache key")?;
+
+    let (key, expired_value) = match cache_backend.read(handler.method_name(), &params_key)? {
+        CacheStatus::Cached { value, .. }
+            if !value.is_expired()
+                && common::parse_block_number(&value.data)
+                    .is_some_and(|cached| cached >= latest) =>
+        {
+            return Ok(());
+        }
+        CacheStatus::Cached { key, value } => (key, Some(value)),
+        CacheStatus::Missed { key } => (key, None),
+    };
+
+    let data = json!(format!("0x{latest:x}"));
+    let (_, value) = handler.extract_cache_value(data, cache_backend.get_reorg_ttl())?;
+    cache_backend.write(&key, value, &expired_value)
+}
+
+/// Caches the head blocks the way `eth_getBlockByNumber(number, false)` would, so that head
+/// lookups resolved to a number are hits, on every instance sharing the cache.
+fn cache_heads(
+    tracker: &HeadTracker,
+    cache_backend: &mut dyn CacheBackend,
+    heads: impl IntoIterator<Item = (BlockRef, Value)>,
+) -> anyhow::Result<()> {
+    let reorg_ttl = cache_backend.get_reorg_ttl();
+    let Some(settled_below) = tracker.oldest_unsettled() else {
+        return Ok(());
+    };
+    if reorg_ttl == 0 {
+        return Ok(());
+    }
+
+    let handler = EthGetBlockByNumber;
+    for (block, data) in heads {
+        // from an upstream lagging on another fork
+        if tracker
+            .canonical_hash(block.number)
+            .is_some_and(|hash| hash != block.hash)
+        {
+            continue;
+        }
+
+        let params = json!([format!("0x{:x}", block.number), false]);
+        let params_key = handler
+            .extract_cache_key(&params)?
+            .context("block number without cache key")?;
+
+        let (key, expired_value) = match cache_backend.read(handler.method_name(), &params_key)? {
+            CacheStatus::Cached { value, .. } if !value.is_expired() => continue,
+            CacheStatus::Cached { key, value } => (key, Some(value)),
+            CacheStatus::Missed { key } => (key, None),
+        };
+
+        let (_, value) = handler.extract_cache_value(data, reorg_ttl)?;
+        let settled = block.number < settled_below;
+        cache_backend.write(
+            &key,
+            value.with_block(block.number, settled),
+            &expired_value,
+        )?;
+        if !settled {
+            cache_backend.tag(&key, block.number..=block.number)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Lets the cache keep the entries of the blocks which became too deep to be reorganized.
+fn settle(
+    tracker: &HeadTracker,
+    cache_factory: &dyn CacheBackendFactory,
+    settled_below: &mut Option<u64>,
+) -> anyhow::Result<()> {
+    let Some(oldest) = tracker.oldest_unsettled() else {
+        return Ok(());
+    };
+
+    // entries of blocks settled before the first poll were never tagged
+    let from = settled_below.unwrap_or(oldest).min(oldest);
+    if from < oldest || settled_below.is_none() {
+        let mut cache_backend = cache_factory
+            .get_instance()
+            .context("fail to get cache backend")?;
+        cache_backend.settle(from..oldest)?;
+    }
+
+    *settled_below = Some(oldest);
+    Ok(())
+}
+
+/// Fetches the pending block from the upstream, without transactions. `None` when the upstream
+/// doesn't serve it.
+async fn get_pending(
+    upstream: &Upstream,
+    client: &reqwest::Client,
+    timeout: Duration,
+) -> Option<Value> {
+    let request = RpcRequest::new_uncachable(
+        0,
+        RequestId::try_from(json!(0)).expect("numeric id"),
+        "eth_getBlockByNumber".to_string(),
+        json!(["pending", false]),
+    );
+
+    let mut responses = upstream
+        .send_direct(client, &[request], timeout)
+        .await
+        .ok()?;
+    let block = responses.pop()?["result"].take();
+    block.is_object().then_some(block)
+}
+
+/// Fetches the blocks at the given tags or numbers from the upstream, without transactions,
+/// along with their data. Blocks the upstream doesn't know about, e.g. `safe` on chains without it, are `None`.
+async fn get_blocks(
+    upstream: &Upstream,
+    client: &reqwest::Client,
+    timeout: Duration,
+    tags: impl IntoIterator<Item = Value>,
+) -> anyhow::Result<Vec<Option<(BlockRef, Value)>>> {
+    let requests = tags
+        .into_iter()
+        .enumerate()
+        .map(|(index, tag)| {
+            RpcRequest::new_uncachable(
+                index,
+                RequestId::try_from(json!(index)).expect("numeric id"),
+                "eth_getBlockByNumber".to_string(),
+                json!([tag, false]),
+            )
+        })
+        .collect::<Vec<_>>();
+
+    let responses = upstream.send_direct(client, &requests, timeout).await?;
+    let mut blocks: HashMap<RequestId, Value> = responses
+        .into_iter()
+        .filter_map(|response| Some((RequestId::try_from(response["id"].clone()).ok()?, response)))
+        .collect();
+
+    requests
+        .iter()
+        .map(|request| match blocks.remove(&request.id) {
+            Some(mut response) if response["result"].is_object() => {
+                let block = BlockRef::from_block(&response["result"])?;
+                Ok(Some((block, response["result"].take())))
+            }
+            _ => Ok(None),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+impl HeadTracker {
+    /// A tracker which saw the given head numbers.
+    pub fn at(latest: u64, safe: Option<u64>, finalized: Option<u64>) -> Self {
+        let block = |number| BlockRef {
+            number,
+            hash: format!("{number:#066x}"),
+            parent_hash: format!("{:#066x}", number.saturating_sub(1)),
+        };
+
+        let heads = Heads {
+            latest,
+            safe,
+            finalized,
+        };
+        let tracker = Self::default();
+        tracker.apply(heads, block(latest), vec![]);
+        tracker
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::cache::memory_backend::MemoryBackendFactory;
+    use crate::config::UpstreamConfig;
+    use crate::upstream::testing::{answer, fake_upstream};
+    use crate::upstream::{
+        BalancePolicy, PoolOptions, QuorumConfig, RetryPolicy, Timeouts, UpstreamPool,
+    };
+
+    fn heads(latest: u64, finalized: Option<u64>) -> Heads {
+        Heads {
+            latest,
+            safe: None,
+            finalized,
+        }
+    }
+
+    fn block(number: u64, fork: char) -> BlockRef {
+        BlockRef {
+            number,
+            hash: format!("{fork}{number}"),
+            parent_hash: format!("{fork}{}", number.wrapping_sub(1)),
+        }
+    }
+
+    #[test]
+    fn test_follow() {
+        let tracker = HeadTracker::default();
+        assert_eq!(tracker.missing(&[block(100, 'a')]), None);
+        assert!(tracker
+            .apply(heads(100, Some(90)), block(100, 'a'), vec![])
+            .is_empty());
+
+        assert_eq!(tracker.resolve("latest"), Some(100));
+        assert_eq!(tracker.resolve("finalized"), Some(90));
+        assert_eq!(tracker.resolve("safe"), None);
+
+        // linked to the known head
+        assert_eq!(tracker.missing(&[block(101, 'a')]), None);
+
+        // a gap above the known head
+        assert_eq!(tracker.missing(&[block(104, 'a')]), Some((101, 103)));
+        let blocks = vec![
+            block(104, 'a'),
+            block(101, 'a'),
+            block(102, 'a'),
+            block(103, 'a'),
+        ];
+        assert_eq!(tracker.missing(&blocks), None);
+        assert!(tracker
+            .apply(heads(104, Some(90)), block(104, 'a'), blocks)
+            .is_empty());
+
+        assert_eq!(tracker.canonical_hash(102).as_deref(), Some("a102"));
+        assert_eq!(tracker.resolve("finalized"), Some(90));
+    }
+
+    #[test]
+    fn test_reorg() {
+        let tracker = HeadTracker::default();
+        let blocks = (95..100).map(|number| block(number, 'a')).collect();
+        tracker.apply(heads(100, None), block(100, 'a'), blocks);
+
+        // block 99 is replaced
+        let mut reorg = block(100, 'b');
+        reorg.parent_hash = "b99".to_string();
+        assert_eq!(tracker.missing(&[reorg.clone()]), Some((95, 99)));
+
+        let mut b99 = block(99, 'b');
+        b99.parent_hash = "a98".to_string();
+        let blocks = vec![reorg.clone(), b99];
+        assert_eq!(tracker.missing(&blocks), None);
+
+        assert_eq!(
+            tracker.apply(heads(100, None), reorg, blocks),
+            vec![99, 100]
+        );
+        assert_eq!(tracker.canonical_hash(99).as_deref(), Some("b99"));
+        assert_eq!(tracker.canonical_hash(98).as_deref(), Some("a98"));
+    }
+
+    #[test]
+    fn test_lagging_poll() {
+        let tracker = HeadTracker::default();
+        let blocks = (95..100).map(|number| block(number, 'a')).collect();
+        tracker.apply(heads(100, Some(90)), block(100, 'a'), blocks);
+
+        // upstreams behind the one that failed aren't a reorg, nor forget the blocks above, but
+        // the tags follow them
+        assert!(tracker
+            .apply(heads(98, Some(88)), block(98, 'a'), vec![])
+            .is_empty());
+        assert_eq!(tracker.canonical_hash(100).as_deref(), Some("a100"));
+        assert_eq!(tracker.resolve("latest"), Some(98));
+        assert_eq!(tracker.resolve("finalized"), Some(88));
+
+        // nor when its blocks are on an orphaned fork
+        assert!(tracker
+            .apply(heads(99, None), block(99, 'b'), vec![block(98, 'b')])
+            .is_empty());
+        assert_eq!(tracker.canonical_hash(99).as_deref(), Some("a99"));
+    }
+
+    #[test]
+    fn test_pin() {
+        let at = |latest, safe, finalized| Heads {
+            latest,
+            safe,
+            finalized,
+        };
+
+        // the tags point at blocks every healthy upstream has
+        let polls = [
+            (true, at(100, Some(94), Some(90))),
+            (true, at(98, None, Some(89))),
+            (false, at(60, Some(50), Some(40))),
+        ];
+        assert_eq!(Heads::pin(&polls), Some(at(98, Some(94), Some(89))));
+
+        let polls = [(false, at(100, None, None)), (false, at(99, None, None))];
+        assert_eq!(Heads::pin(&polls), Some(at(99, None, None)));
+
+        assert_eq!(Heads::pin(&[]), None);
+    }
+
+    #[test]
+    fn test_stale() {
+        let tracker = HeadTracker::new(Duration::ZERO);
+        assert_eq!(tracker.resolve("latest"), None);
+
+        tracker.apply(heads(100, None), block(100, 'a'), vec![]);
+        std::thread::sleep(Duration::from_millis(1));
+        assert_eq!(tracker.resolve("latest"), None);
+    }
+
+    #[test]
+    fn test_cache_block_number() {
+        let cache_factory = MemoryBackendFactory::new(12);
+        let mut cache_backend = cache_factory.get_instance().unwrap();
+        let cached = |cache_backend: &mut Box<dyn CacheBackend>| match cache_backend
+            .read("eth_blockNumber", "eth_blockNumber")
+            .unwrap()
+        {
+            CacheStatus::Cached { value, .. } => Some(value.data),
+            CacheStatus::Missed { .. } => None,
+        };
+
+        cache_block_number(&HeadTracker::at(0x100, None, None), &mut *cache_backend).unwrap();
+        assert_eq!(cached(&mut cache_backend), Some(json!("0x100")));
+
+        // an instance whose upstreams lag doesn't move it back
+        cache_block_number(&HeadTracker::at(0xff, None, None), &mut *cache_backend).unwrap();
+        assert_eq!(cached(&mut cache_backend), Some(json!("0x100")));
+
+        cache_block_number(&HeadTracker::at(0x101, None, None), &mut *cache_backend).unwrap();
+        assert_eq!(cached(&mut cache_backend), Some(json!("0x101")));
+    }
+
+    #[test]
+    fn test_pending() {
+        let tracker = HeadTracker::new(Duration::ZERO);
+        tracker.state.write().unwrap().pending = Some(json!({"number": "0x65"}));
+        tracker.apply(heads(100, None), block(100, 'a'), vec![]);
+        std::thread::sleep(Duration::from_millis(1));
+        assert_eq!(tracker.pending(), None);
+
+        let tracker = HeadTracker::at(100, None, None);
+        tracker.state.write().unwrap().pending = Some(json!({"number": "0x65"}));
+        assert_eq!(tracker.pending(), Some(json!({"number": "0x65"})));
+    }
+
+    #[test]
+    fn test_unsettled() {
+        let tracker = HeadTracker::at(1000, None, Some(990));
+        assert_eq!(tracker.unsettled((900, 995)), Some((991, 995)));
+        assert_eq!(tracker.unsettled((100, 200)), None);
+
+        // finality far behind the recent blocks, a reorganization deeper than them goes unnoticed
+        let tracker = HeadTracker::at(1000, None, Some(500));
+        assert_eq!(tracker.oldest_unsettled(), Some(501));
+        assert_eq!(tracker.unsettled((400, 600)), None);
+        assert_eq!(tracker.unsettled((400, 900)), Some((873, 900)));
+
+        let tracker = HeadTracker::at(1000, None, None);
+        assert_eq!(tracker.oldest_unsettled(), Some(873));
+        assert_eq!(tracker.unsettled((900, u64::MAX)), Some((900, 1128)));
+
+        assert_eq!(HeadTracker::default().unsettled((1, 2)), None);
+    }
+
+    /// An upstream whose chain is at `head`, without safe nor finalized blocks.
+    fn chain_at(head: u64) -> UpstreamConfig {
+        fake_upstream(answer(move |request| {
+            let number = match request["params"][0].as_str().unwrap() {
+                "latest" => head,
+                "safe" | "finalized" => return Value::Null,
+                number => u64::from_str_radix(number.trim_start_matches("0x"), 16).unwrap(),
+            };
+            match number <= head {
+                true => json!({
+                    "number": format!("{number:#x}"),
+                    "hash": format!("{number:#066x}"),
+                    "parentHash": format!("{:#066x}", number - 1),
+                }),
+                false => Value::Null,
+            }
+        }))
+    }
+
+    #[actix_web::test]
+    async fn test_poll_upstreams_apart() {
+        // upstreams a block apart disagree on the head, a quorum read would fail
+        let quorum: QuorumConfig = serde_json::from_value(json!({"size": 2})).unwrap();
+        let options = PoolOptions {
+            policy: BalancePolicy::Failover,
+            retry: RetryPolicy::default(),
+            timeouts: Timeouts::new(Duration::from_secs(5), Default::default()),
+            hedge: None,
+            quorum: Some(quorum),
+            breaker: None,
+        };
+        let upstreams = vec![chain_at(98), chain_at(100)];
+        let pool = UpstreamPool::new("ETH".to_string(), upstreams, vec![], options).unwrap();
+
+        let tracker = HeadTracker::new(Duration::from_secs(60));
+        let cache_factory = MemoryBackendFactory::new(12);
+        let client = reqwest::Client::new();
+        let metrics = Metrics::new("test");
+
+        let timeout = Duration::from_secs(5);
+        poll(&tracker, &pool, &cache_factory, &client, &metrics, timeout)
+            .await
+            .unwrap();
+
+        // the chain is followed along the highest head, the tags point at the lowest one
+        assert_eq!(tracker.canonical_hash(100), Some(format!("{:#066x}", 100)));
+        assert_eq!(tracker.resolve("latest"), Some(98));
+    }
+}
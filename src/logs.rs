@@ -0,0 +1,431 @@
+use std::cmp::Ordering;
+
+use futures::future;
+use serde_json::{json, Map, Value};
+
+use crate::cache::CacheStatus;
+use crate::json_rpc::RequestId;
+use crate::metrics::Metrics;
+use crate::rpc_cache_handler::common;
+use crate::{write_cache_entry, ChainState, RpcRequest};
+
+/// Wording of the errors providers return for a log query whose range or result is too large.
+const TOO_LARGE_ERRORS: [&str; 7] = [
+    "block range",
+    "range is too",
+    "range too",
+    "too many",
+    "more than",
+    "response size",
+    "limited to",
+];
+
+/// Most chunks a query is stitched from, each of them is a cache lookup. Larger ranges are
+/// fetched and cached as a whole.
+const MAX_CHUNKS: u64 = 64;
+
+/// Caches the logs of each filter in chunks of `size` aligned blocks, so that overlapping and
+/// sliding ranges share them. A range query is stitched from the cached chunks and only the
+/// missing ones are fetched, split further while the upstream finds the range too large.
+pub struct LogChunks {
+    size: u64,
+}
+
+impl LogChunks {
+    pub fn new(size: u64) -> Self {
+        Self { size }
+    }
+
+    /// Whether the request is an `eth_getLogs` over a range of block numbers.
+    pub fn matches(&self, request: &RpcRequest) -> bool {
+        request.method == "eth_getLogs" && bounds(&request.params).is_some()
+    }
+
+    /// Whether the logs of the request are cached in chunks, rather than as a whole.
+    pub fn caches_chunks(&self, request: &RpcRequest) -> bool {
+        match bounds(&request.params) {
+            Some((from, to)) => self.matches(request) && self.chunks(from, to) <= MAX_CHUNKS,
+            None => false,
+        }
+    }
+
+    /// Answers a range query, as a JSON-RPC response and whether all of it was confirmed by the
+    /// quorum.
+    pub async fn fetch(
+        &self,
+        chain_state: &ChainState,
+        client: &reqwest::Client,
+        metrics: &Metrics,
+        request: &RpcRequest,
+    ) -> (Value, bool) {
+        let (from, to) = bounds(&request.params).expect("range query");
+        let filter = &request.params[0];
+        let chain = chain_state.upstreams.chain();
+
+        // too many chunks to look up, the range is only paginated and cached as a whole
+        if !self.caches_chunks(request) {
+            let fetched = fetch_range(chain_state, client, metrics, filter, (from, to)).await;
+            return match fetched {
+                Ok((mut logs, confirmed)) => {
+                    logs.sort_by(compare_logs);
+                    let response = json!({"jsonrpc": "2.0", "id": request.id, "result": logs});
+                    (response, confirmed)
+                }
+                Err(error) => {
+                    let response = json!({"jsonrpc": "2.0", "id": request.id, "error": error});
+                    (response, false)
+                }
+            };
+        }
+
+        let (mut logs, missing) = self.read_chunks(chain_state, filter, from, to);
+        let hits = self.chunks(from, to) as usize - missing.len();
+        metrics
+            .logs_chunk_counter
+            .with_label_values(&[chain, "hit"])
+            .inc_by(hits as u64);
+        metrics
+            .logs_chunk_counter
+            .with_label_values(&[chain, "miss"])
+            .inc_by(missing.len() as u64);
+
+        // contiguous missing chunks are fetched at once
+        let mut ranges: Vec<(u64, u64)> = vec![];
+        for (start, end) in missing {
+            match ranges.last_mut() {
+                Some(range) if range.1 + 1 == start => range.1 = end,
+                _ => ranges.push((start, end)),
+            }
+        }
+
+        let fetched = future::join_all(
+            ranges
+                .iter()
+                .map(|&range| fetch_range(chain_state, client, metrics, filter, range)),
+        )
+        .await;
+
+        let mut confirmed = true;
+        let mut chunks = vec![];
+        for (range, fetched) in ranges.into_iter().zip(fetched) {
+            let (fetched, range_confirmed) = match fetched {
+                Ok(fetched) => fetched,
+                Err(error) => {
+                    let response = json!({"jsonrpc": "2.0", "id": request.id, "error": error});
+                    return (response, false);
+                }
+            };
+
+            confirmed &= range_confirmed;
+            logs.extend(
+                fetched
+                    .iter()
+                    .filter(|log| log_block(log).is_some_and(|block| (from..=to).contains(&block)))
+                    .cloned(),
+            );
+            chunks.push((range, fetched));
+        }
+
+        if confirmed {
+            if let Err(err) = self.write_chunks(chain_state, filter, chunks) {
+                metrics.error_counter.inc();
+                tracing::error!("fail to write log chunks because: {err:#}");
+            }
+        }
+
+        logs.sort_by(compare_logs);
+        let response = json!({"jsonrpc": "2.0", "id": request.id, "result": logs});
+        (response, confirmed)
+    }
+
+    /// Logs of the cached chunks within the range, and the ranges to fetch for the others.
+    /// Missing chunks are fetched whole to be cached, unless they reach past the head, i.e. the
+    /// lowest head of the healthy upstreams.
+    fn read_chunks(
+        &self,
+        chain_state: &ChainState,
+        filter: &Value,
+        from: u64,
+        to: u64,
+    ) -> (Vec<Value>, Vec<(u64, u64)>) {
+        let mut cache_backend = chain_state.cache_factory.get_instance().ok();
+        let latest = chain_state.head.resolve("latest");
+
+        let mut logs = vec![];
+        let mut missing = vec![];
+        for chunk in from / self.size..=to / self.size {
+            let (start, end) = self.chunk_range(chunk);
+
+            let cached = cache_backend.as_mut().and_then(|cache_backend| {
+                let params = chunk_params(filter, start, end);
+                crate::read_cached(chain_state, &mut **cache_backend, "eth_getLogs", &params)
+            });
+
+            match cached {
+                Some(Value::Array(cached)) => logs.extend(cached.into_iter().filter(|log| {
+                    log_block(log).is_some_and(|block| (from..=to).contains(&block))
+                })),
+                _ => match latest {
+                    Some(latest) if end <= latest => missing.push((start, end)),
+                    _ => missing.push((start.max(from), end.min(to))),
+                },
+            }
+        }
+
+        (logs, missing)
+    }
+
+    /// Caches the whole chunks of the fetched ranges, once every healthy upstream has their last
+    /// block: a chunk reaching past the head of the upstream which served it would miss logs.
+    fn write_chunks(
+        &self,
+        chain_state: &ChainState,
+        filter: &Value,
+        fetched: Vec<((u64, u64), Vec<Value>)>,
+    ) -> anyhow::Result<()> {
+        let Some(handler) = chain_state.handlers.get("eth_getLogs") else {
+            return Ok(());
+        };
+        let mut cache_backend = chain_state.cache_factory.get_instance()?;
+        let Some(latest) = chain_state.head.resolve("latest") else {
+            return Ok(());
+        };
+
+        for ((from, to), logs) in fetched {
+            for chunk in from / self.size..=to / self.size {
+                let (start, end) = self.chunk_range(chunk);
+                if start < from || end > to.min(latest) {
+                    continue;
+                }
+
+                let params = chunk_params(filter, start, end);
+                let Some(params_key) = handler.extract_cache_key(&params)? else {
+                    continue;
+                };
+                let (key, expired_value) = match cache_backend.read("eth_getLogs", &params_key)? {
+                    CacheStatus::Cached { key, value } => (key, Some(value)),
+                    CacheStatus::Missed { key } => (key, None),
+                };
+
+                let logs = logs
+                    .iter()
+                    .filter(|log| {
+                        log_block(log).is_some_and(|block| (start..=end).contains(&block))
+                    })
+                    .cloned()
+                    .collect();
+                let reorg_ttl = cache_backend.get_reorg_ttl();
+                let (_, value) = handler.extract_cache_value(Value::Array(logs), reorg_ttl)?;
+                write_cache_entry(
+                    &chain_state.head,
+                    &mut *cache_backend,
+                    &key,
+                    value,
+                    &expired_value,
+                    Some((start, end)),
+                )?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn chunks(&self, from: u64, to: u64) -> u64 {
+        to / self.size - from / self.size + 1
+    }
+
+    fn chunk_range(&self, chunk: u64) -> (u64, u64) {
+        let start = chunk * self.size;
+        (start, start.saturating_add(self.size - 1))
+    }
+}
+
+/// Fetches the logs of a range, halving it while the upstream finds it too large. Returns the
+/// logs and whether all of them were confirmed by the quorum, or the error of the upstream.
+async fn fetch_range(
+    chain_state: &ChainState,
+    client: &reqwest::Client,
+    metrics: &Metrics,
+    filter: &Value,
+    range: (u64, u64),
+) -> Result<(Vec<Value>, bool), Value> {
+    let mut pending = vec![range];
+    let mut logs = vec![];
+    let mut confirmed = true;
+
+    while let Some((from, to)) = pending.pop() {
+        let id = RequestId::try_from(json!(0)).expect("numeric id");
+        let params = json!([with_bounds(filter, from, to)]);
+        let request = RpcRequest::new_uncachable(0, id.clone(), "eth_getLogs".to_string(), params);
+
+        let responses = chain_state
+            .send_upstream(client, metrics, vec![request])
+            .await;
+        confirmed &= responses.is_cacheable(&id);
+        let Some(mut response) = responses.values.into_iter().next() else {
+            return Err(json!({"code": -32603, "message": "no response from upstream"}));
+        };
+
+        match response["error"].take() {
+            Value::Null => match response["result"].take() {
+                Value::Array(result) => logs.extend(result),
+                _ => return Err(json!({"code": -32603, "message": "logs are not an array"})),
+            },
+            error if to > from && is_too_large(&error) => {
+                metrics
+                    .logs_split_counter
+                    .with_label_values(&[chain_state.upstreams.chain()])
+                    .inc();
+                let middle = from + (to - from) / 2;
+                pending.push((middle + 1, to));
+                pending.push((from, middle));
+            }
+            error => return Err(error),
+        }
+    }
+
+    Ok((logs, confirmed))
+}
+
+fn is_too_large(error: &Value) -> bool {
+    let message = error["message"].as_str().unwrap_or_default().to_lowercase();
+    TOO_LARGE_ERRORS.iter().any(|words| message.contains(words))
+}
+
+/// Block numbers of a query over a range, `None` for a block hash or unresolved tags.
+fn bounds(params: &Value) -> Option<(u64, u64)> {
+    let filter = params.get(0)?;
+    if !filter["blockHash"].is_null() {
+        return None;
+    }
+
+    let from = common::parse_block_number(&filter["fromBlock"])?;
+    let to = common::parse_block_number(&filter["toBlock"])?;
+    (from <= to).then_some((from, to))
+}
+
+/// The filter over another range.
+fn with_bounds(filter: &Value, from: u64, to: u64) -> Value {
+    let mut filter = filter.clone();
+    filter["fromBlock"] = json!(format!("0x{from:x}"));
+    filter["toBlock"] = json!(format!("0x{to:x}"));
+    filter
+}
+
+/// Params of a chunk, with the filter reduced to its addresses and topics in lowercase, so that
+/// equivalent queries share the chunk.
+fn chunk_params(filter: &Value, from: u64, to: u64) -> Value {
+    fn lowercase(value: &Value) -> Value {
+        match value {
+            Value::String(value) => Value::String(value.to_lowercase()),
+            Value::Array(values) => values.iter().map(lowercase).collect(),
+            value => value.clone(),
+        }
+    }
+
+    let mut normalized = Map::new();
+    for field in ["address", "topics"] {
+        if let Some(value) = filter.get(field).filter(|value| !value.is_null()) {
+            normalized.insert(field.to_string(), lowercase(value));
+        }
+    }
+
+    json!([with_bounds(&Value::Object(normalized), from, to)])
+}
+
+fn log_block(log: &Value) -> Option<u64> {
+    common::parse_block_number(&log["blockNumber"])
+}
+
+fn compare_logs(a: &Value, b: &Value) -> Ordering {
+    let index = |log: &Value| common::parse_block_number(&log["logIndex"]);
+    (log_block(a), index(a)).cmp(&(log_block(b), index(b)))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_bounds() {
+        let params = json!([{"fromBlock": "0x10", "toBlock": "0x20"}]);
+        assert_eq!(bounds(&params), Some((0x10, 0x20)));
+
+        assert_eq!(
+            bounds(&json!([{"fromBlock": "0x10", "toBlock": "latest"}])),
+            None
+        );
+        assert_eq!(
+            bounds(&json!([{"fromBlock": "0x20", "toBlock": "0x10"}])),
+            None
+        );
+        assert_eq!(
+            bounds(&json!([{"fromBlock": "0x10", "toBlock": "0x20", "blockHash": "0x12"}])),
+            None
+        );
+    }
+
+    #[test]
+    fn test_caches_chunks() {
+        let request = |from: u64, to: u64| {
+            let params =
+                json!([{"fromBlock": format!("{from:#x}"), "toBlock": format!("{to:#x}")}]);
+            let id = RequestId::try_from(json!(1)).unwrap();
+            RpcRequest::new_uncachable(0, id, "eth_getLogs".to_string(), params)
+        };
+        let chunks = LogChunks::new(100);
+
+        assert!(chunks.caches_chunks(&request(150, 250)));
+        assert!(chunks.caches_chunks(&request(0, 6399)));
+
+        // a range over too many chunks is still split, but cached as a whole
+        assert!(chunks.matches(&request(0, 6400)));
+        assert!(!chunks.caches_chunks(&request(0, 6400)));
+    }
+
+    #[test]
+    fn test_chunk_params() {
+        let filter = json!({
+            "fromBlock": "0x15",
+            "toBlock": "0x30",
+            "address": "0xB59F67A8BFF5D8CD03F6AC17265C550ED8F33907",
+            "topics": [["0xDDF2"], null],
+        });
+
+        assert_eq!(
+            chunk_params(&filter, 0x10, 0x1f),
+            json!([{
+                "fromBlock": "0x10",
+                "toBlock": "0x1f",
+                "address": "0xb59f67a8bff5d8cd03f6ac17265c550ed8f33907",
+                "topics": [["0xddf2"], null],
+            }])
+        );
+    }
+
+    #[test]
+    fn test_too_large() {
+        for message in [
+            "query returned more than 10000 results",
+            "Log response size exceeded. You can make eth_getLogs requests with up to a 2K block range",
+            "block range is too wide",
+        ] {
+            assert!(is_too_large(&json!({"code": -32000, "message": message})));
+        }
+
+        assert!(!is_too_large(
+            &json!({"code": -32000, "message": "execution reverted"})
+        ));
+    }
+
+    #[test]
+    fn test_compare_logs() {
+        let log = |block: &str, index: &str| json!({"blockNumber": block, "logIndex": index});
+        let mut logs = vec![log("0x2", "0x0"), log("0x1", "0x10"), log("0x1", "0x2")];
+        logs.sort_by(compare_logs);
+        assert_eq!(
+            logs,
+            vec![log("0x1", "0x2"), log("0x1", "0x10"), log("0x2", "0x0")]
+        );
+    }
+}
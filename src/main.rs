@@ -1,28 +1,44 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+use std::time::Duration;
 
 use actix_web::{error, web, App, Error, HttpRequest, HttpResponse, HttpServer};
 use anyhow::Context;
 use cache::{lru_backend, memory_backend, CacheBackendFactory};
 use clap::Parser;
 use env_logger::Env;
-use reqwest::Url;
+use futures::future;
 use serde::Serialize;
 use serde_json::{json, Value};
 use tracing::info;
 
 use crate::args::Args;
+use crate::batcher::Batcher;
 use crate::cache::redis_backend::RedisBackendFactory;
-use crate::cache::{CacheStatus, CacheValue};
+use crate::cache::{CacheBackend, CacheStatus, CacheValue};
+use crate::coalesce::{Coalescer, Flight};
+use crate::config::Config;
+use crate::head_tracker::HeadTracker;
 use crate::json_rpc::{DefinedError, JsonRpcRequest, JsonRpcResponse, RequestId};
-use crate::rpc_cache_handler::RpcCacheHandler;
+use crate::logs::LogChunks;
+use crate::rpc_cache_handler::{common, RpcCacheHandler};
+use crate::upstream::{
+    BreakerPolicy, HedgeConfig, PoolOptions, Responses, RetryPolicy, Timeouts, UpstreamPool,
+};
 
 use tracing::debug;
 
 mod args;
+mod batcher;
 mod cache;
+mod coalesce;
+mod config;
+mod head_tracker;
 mod json_rpc;
+mod logs;
 mod metrics;
 mod rpc_cache_handler;
+mod upstream;
 mod utils;
 
 // Health check handler
@@ -40,8 +56,11 @@ async fn rpc_call(
     body: web::Json<Value>,
 ) -> Result<HttpResponse, Error> {
     let metrics = &data.metrics;
-    let conn_info = req.connection_info();
-    let client_ip = conn_info.realip_remote_addr().unwrap_or("unknown");
+    let client_ip = req
+        .connection_info()
+        .realip_remote_addr()
+        .unwrap_or("unknown")
+        .to_string();
     let (chain,) = path.into_inner();
 
     info!(
@@ -85,7 +104,7 @@ async fn rpc_call(
 
         // iterate through each request looking for the result in cache and aggregating uncached requests
         for (index, request) in requests.into_iter().enumerate() {
-            let (id, method, params) = match extract_single_request_info(request) {
+            let (id, method, mut params) = match extract_single_request_info(request) {
                 Ok(v) => v,
                 Err((request_id, err)) => {
                     ordered_requests_result
@@ -113,7 +132,10 @@ async fn rpc_call(
                     let rpc_request = RpcRequest::new_uncachable(index, id, method.clone(), params);
                     request_id_index_map.insert(rpc_request.id.clone(), uncached_requests.len());
                     uncached_requests.push((rpc_request, None));
-                    metrics.method_call_counter.with_label_values(&[&chain, &method, "miss"]).inc();
+                    metrics
+                        .method_call_counter
+                        .with_label_values(&[&chain, &method, "miss"])
+                        .inc();
                     continue;
                 }};
 
@@ -121,7 +143,10 @@ async fn rpc_call(
                     let rpc_request = RpcRequest::new(index, id, method.clone(), params, $key);
                     request_id_index_map.insert(rpc_request.id.clone(), uncached_requests.len());
                     uncached_requests.push((rpc_request, None));
-                    metrics.method_call_counter.with_label_values(&[&chain, &method, "miss"]).inc();
+                    metrics
+                        .method_call_counter
+                        .with_label_values(&[&chain, &method, "miss"])
+                        .inc();
                     continue;
                 }};
 
@@ -129,7 +154,10 @@ async fn rpc_call(
                     let rpc_request = RpcRequest::new(index, id, method.clone(), params, $key);
                     request_id_index_map.insert(rpc_request.id.clone(), uncached_requests.len());
                     uncached_requests.push((rpc_request, Some($val)));
-                    metrics.method_call_counter.with_label_values(&[&chain, &method, "miss"]).inc();
+                    metrics
+                        .method_call_counter
+                        .with_label_values(&[&chain, &method, "miss"])
+                        .inc();
                     continue;
                 }};
             }
@@ -144,6 +172,20 @@ async fn rpc_call(
                 }
             };
 
+            // pin symbolic block tags to the current head, for the cache key and the upstream alike
+            handler.resolve_block_tags(&mut params, &chain_state.head);
+
+            // the tracked head answers head lookups, the same way for every caller
+            if let Some(result) = handler.answer_from_head(&params, &chain_state.head) {
+                metrics.cache_hit_counter.inc();
+                metrics
+                    .method_call_counter
+                    .with_label_values(&[&chain, &method, "hit"])
+                    .inc();
+                ordered_requests_result[index] = Some(JsonRpcResponse::from_result(id, result));
+                continue;
+            }
+
             // get the cache key from the handler based on the request params
             debug!("params: {:?}", params);
             let params_key = match handler.extract_cache_key(&params) {
@@ -165,7 +207,10 @@ async fn rpc_call(
                 Ok(CacheStatus::Cached { key, value }) => {
                     if !value.is_expired() {
                         metrics.cache_hit_counter.inc();
-                        metrics.method_call_counter.with_label_values(&[&chain, &method, "hit"]).inc();
+                        metrics
+                            .method_call_counter
+                            .with_label_values(&[&chain, &method, "hit"])
+                            .inc();
                         tracing::info!("cache hit for method {} with key {}", method, key);
                         ordered_requests_result[index] =
                             Some(JsonRpcResponse::from_result(id, value.data));
@@ -177,6 +222,49 @@ async fn rpc_call(
                     }
                 }
                 Ok(CacheStatus::Missed { key }) => {
+                    // e.g. the receipts of a block from the receipts of its transactions
+                    let assembled = handler.assemble_from_cache(&params, &mut |other, params| {
+                        read_cached(chain_state, &mut *cache_backend, other, &params)
+                    });
+                    if let Some(result) = assembled {
+                        metrics.cache_hit_counter.inc();
+                        metrics
+                            .method_call_counter
+                            .with_label_values(&[&chain, &method, "hit"])
+                            .inc();
+                        tracing::info!("cache assembled for method {} with key {}", method, key);
+
+                        let block_range = block_range(
+                            chain_state,
+                            &mut *cache_backend,
+                            handler,
+                            &params,
+                            &result,
+                        );
+                        let reorg_ttl = cache_backend.get_reorg_ttl();
+                        let written = handler
+                            .extract_cache_value(result.clone(), reorg_ttl)
+                            .and_then(|(is_cacheable, value)| match is_cacheable {
+                                true => write_cache_entry(
+                                    &chain_state.head,
+                                    &mut *cache_backend,
+                                    &key,
+                                    value,
+                                    &None,
+                                    block_range,
+                                ),
+                                false => Ok(()),
+                            });
+                        if let Err(err) = written {
+                            metrics.error_counter.inc();
+                            tracing::error!("fail to write cache entry because: {err:#}");
+                        }
+
+                        ordered_requests_result[index] =
+                            Some(JsonRpcResponse::from_result(id, result));
+                        continue;
+                    }
+
                     metrics.cache_miss_counter.inc();
                     tracing::info!("cache missed for method {} with key {}", method, key);
                     push_uncached_request_and_continue!(key);
@@ -204,63 +292,88 @@ async fn rpc_call(
         return_response!();
     }
 
-    let rpc_requests: Vec<RpcRequest> = uncached_requests
+    // misses of a key already requested upstream by another call wait for its response
+    let mut leaders = HashMap::new();
+    let mut followers = vec![];
+    let mut sent = vec![];
+    for (index, (rpc_request, _)) in uncached_requests.iter().enumerate() {
+        match rpc_request
+            .cache_key
+            .as_deref()
+            .map(|key| chain_state.inflight.join(key))
+        {
+            Some(Flight::Follower(receiver)) => followers.push((index, receiver)),
+            Some(Flight::Leader(leader)) => {
+                leaders.insert(rpc_request.id.clone(), leader);
+                sent.push(index);
+            }
+            None => sent.push(index),
+        }
+    }
+
+    let rpc_requests: Vec<RpcRequest> = sent
         .iter()
-        .map(|(req, _)| req.clone())
+        .map(|index| uncached_requests[*index].0.clone())
         .collect();
 
-    // prepare rpc and return the result future
-    let rpc_result = utils::do_rpc_request(
-        &data.http_client,
-        chain_state.rpc_url.clone(),
-        &rpc_requests,
-    );
-
-    // await the rpc response, for each cache miss record the response
-    let rpc_result = match rpc_result.await {
-        Ok(v) => v,
-        Err(err) => {
-            metrics.error_counter.inc();
-            tracing::error!("fail to make rpc request because: {}", err);
-
-            for (rpc_request, _) in uncached_requests {
-                ordered_requests_result[rpc_request.index] = Some(JsonRpcResponse::from_error(
-                    Some(rpc_request.id),
-                    DefinedError::InternalError(Some(json!({
-                        "error": "fail to make rpc request to backend",
-                        "reason": err.to_string(),
-                    }))),
-                ));
+    // send the uncached requests upstream, failed requests come back as error responses
+    let leading = async {
+        let responses = chain_state
+            .send(&data.http_client, metrics, rpc_requests)
+            .await;
+
+        for response in &responses.values {
+            let leader = RequestId::try_from(response["id"].clone())
+                .ok()
+                .and_then(|id| leaders.remove(&id));
+            if let Some(leader) = leader {
+                leader.complete(response);
             }
-
-            return_response!();
         }
-    };
+        // leaders left without a response release their followers
+        drop(leaders);
 
-    // unwrap rpc_result into a vector of responses
-    let result_values = match rpc_result {
-        Value::Array(v) => v,
-        _ => {
-            metrics.error_counter.inc();
-            tracing::error!(
-                "array is expected but we got invalid rpc response: {},",
-                rpc_result.to_string()
-            );
-
-            for (rpc_request, _) in uncached_requests {
-                ordered_requests_result[rpc_request.index] = Some(JsonRpcResponse::from_error(
-                    Some(rpc_request.id),
-                    DefinedError::InternalError(Some(json!({
-                        "error": "invalid rpc response from backend",
-                        "reason": "array is expected",
-                        "response": rpc_result.to_string(),
-                    }))),
-                ));
+        responses
+    };
+    let following = future::join_all(
+        followers
+            .into_iter()
+            .map(|(index, receiver)| async move { (index, receiver.await) }),
+    );
+    let (mut responses, shared) = futures::join!(leading, following);
+
+    let mut result_values = std::mem::take(&mut responses.values);
+    let mut coalesced = HashSet::new();
+    let mut orphans = vec![];
+    for (index, response) in shared {
+        let rpc_request = &uncached_requests[index].0;
+        match response {
+            Ok(mut response) => {
+                metrics
+                    .coalesced_counter
+                    .with_label_values(&[&chain, &rpc_request.method])
+                    .inc();
+                response["id"] = json!(rpc_request.id);
+                coalesced.insert(rpc_request.id.clone());
+                result_values.push(response);
             }
-
-            return_response!();
+            // the leading call went away without a response
+            Err(_) => orphans.push(index),
         }
-    };
+    }
+
+    if !orphans.is_empty() {
+        let rpc_requests: Vec<RpcRequest> = orphans
+            .iter()
+            .map(|index| uncached_requests[*index].0.clone())
+            .collect();
+        let orphan_responses = chain_state
+            .send(&data.http_client, metrics, rpc_requests)
+            .await;
+
+        result_values.extend(orphan_responses.values);
+        responses.unconfirmed.extend(orphan_responses.unconfirmed);
+    }
 
     // ensure we got the expected number of responses
     if result_values.len() != uncached_requests.len() {
@@ -297,22 +410,16 @@ async fn rpc_call(
     // if the response was an error, record an error result and continue
     // else assign the response and extract the cache key for insertion
     // into the cache backend.
-    for (index, mut response) in result_values.into_iter().enumerate() {
+    for mut response in result_values {
         let (rpc_request, cache_value) = match RequestId::try_from(response["id"].clone()) {
-            Ok(id) if request_id_index_map.get(&id).is_some() => {
+            Ok(id) if request_id_index_map.contains_key(&id) => {
                 &uncached_requests[*request_id_index_map.get(&id).unwrap()]
             }
+            // responses come in any order, one can't be told apart by its position
             _ => {
-                if index >= uncached_requests.len() {
-                    metrics.error_counter.inc();
-                    tracing::warn!("rpc response has invalid id and fail to map to original request. response is ignored, response: {response}");
-                    continue;
-                }
-
-                tracing::warn!(
-                    "rpc response has invalid id. find a potential match from original request"
-                );
-                &uncached_requests[index]
+                metrics.error_counter.inc();
+                tracing::warn!("rpc response has invalid id and fail to map to original request. response is ignored, response: {response}");
+                continue;
             }
         };
 
@@ -336,10 +443,35 @@ async fn rpc_call(
             None => continue,
         };
 
+        if !responses.is_cacheable(&rpc_request.id) {
+            continue;
+        }
+
+        // already written by the leading call
+        if coalesced.contains(&rpc_request.id) {
+            continue;
+        }
+
+        // cached in block chunks instead, see `LogChunks`
+        if let Some(logs) = &chain_state.logs {
+            if logs.caches_chunks(rpc_request) {
+                continue;
+            }
+        }
+
         // It's safe to unwrap here because if the cache system doesn't support this method, we have already
         // made the early return.
         let handler = chain_state.handlers.get(&rpc_request.method).unwrap();
 
+        let block_range = block_range(
+            chain_state,
+            &mut *cache_backend,
+            handler,
+            &rpc_request.params,
+            &result,
+        );
+        let derived = handler.derived_entries(&rpc_request.params, &result, &chain_state.head);
+
         let (is_cacheable, extracted_value) =
             match handler.extract_cache_value(result, cache_backend.get_reorg_ttl()) {
                 Ok(v) => v,
@@ -360,13 +492,149 @@ async fn rpc_call(
             };
 
         if is_cacheable {
-            let _ = cache_backend.write(cache_key.as_str(), extracted_value, cache_value);
+            let written = write_cache_entry(
+                &chain_state.head,
+                &mut *cache_backend,
+                &cache_key,
+                extracted_value,
+                cache_value,
+                block_range,
+            );
+
+            // e.g. the block by hash, or the transactions of a full block
+            let written = written.and_then(|_| {
+                derived
+                    .into_iter()
+                    .try_for_each(|(method, params, result)| {
+                        write_derived(chain_state, &mut *cache_backend, method, &params, result)
+                    })
+            });
+            if let Err(err) = written {
+                metrics.error_counter.inc();
+                tracing::error!("fail to write cache entry because: {err:#}");
+            }
         }
     }
 
+    // requests left without a response, e.g. dropped by the upstream
+    for (rpc_request, _) in &uncached_requests {
+        ordered_requests_result[rpc_request.index].get_or_insert_with(|| {
+            JsonRpcResponse::from_error(
+                Some(rpc_request.id.clone()),
+                DefinedError::InternalError(Some(json!({
+                    "error": "no response from backend",
+                }))),
+            )
+        });
+    }
+
     return_response!()
 }
 
+/// Writes an entry, expiring by depth when the head is tracked and tagged with the blocks which
+/// may still be reorganized, so that the entry goes with them.
+fn write_cache_entry(
+    head: &HeadTracker,
+    cache_backend: &mut dyn CacheBackend,
+    key: &str,
+    value: CacheValue,
+    expired_value: &Option<CacheValue>,
+    block_range: Option<(u64, u64)>,
+) -> anyhow::Result<()> {
+    // from an upstream on a fork the head doesn't follow
+    if common::is_orphaned(&value.data, head) {
+        return Ok(());
+    }
+
+    // data of settled blocks is kept for good
+    let value = match (block_range, head.oldest_unsettled()) {
+        (Some((_, to)), Some(settled_below)) if cache_backend.get_reorg_ttl() > 0 => {
+            value.with_block(to, to < settled_below)
+        }
+        _ => value,
+    };
+    cache_backend.write(key, value, expired_value)?;
+
+    if let Some((from, to)) = block_range.and_then(|range| head.unsettled(range)) {
+        cache_backend
+            .tag(key, from..=to)
+            .context("fail to tag cache entry")?;
+    }
+
+    Ok(())
+}
+
+/// Result of another request, if cached and not expired.
+fn read_cached(
+    chain_state: &ChainState,
+    cache_backend: &mut dyn CacheBackend,
+    method: &str,
+    params: &Value,
+) -> Option<Value> {
+    let handler = chain_state.handlers.get(method)?;
+    let params_key = handler.extract_cache_key(params).ok()??;
+    match cache_backend.read(method, &params_key).ok()? {
+        CacheStatus::Cached { value, .. } if !value.is_expired() => Some(value.data),
+        _ => None,
+    }
+}
+
+/// Blocks a result derives from, told by the result itself or else located from the head and the
+/// cached results of other requests.
+fn block_range(
+    chain_state: &ChainState,
+    cache_backend: &mut dyn CacheBackend,
+    handler: &HandlerEntry,
+    params: &Value,
+    result: &Value,
+) -> Option<(u64, u64)> {
+    if let Some(range) = handler.extract_block_range(params, result) {
+        return Some(range);
+    }
+
+    handler.locate_block_range(params, &chain_state.head, &mut |method, params| {
+        read_cached(chain_state, cache_backend, method, &params)
+    })
+}
+
+/// Writes the entry of a request derived from another result, unless it is cached already.
+fn write_derived(
+    chain_state: &ChainState,
+    cache_backend: &mut dyn CacheBackend,
+    method: &str,
+    params: &Value,
+    result: Value,
+) -> anyhow::Result<()> {
+    let Some(handler) = chain_state.handlers.get(method) else {
+        return Ok(());
+    };
+    let Some(params_key) = handler.extract_cache_key(params)? else {
+        return Ok(());
+    };
+
+    let (key, expired_value) = match cache_backend.read(method, &params_key)? {
+        CacheStatus::Cached { value, .. } if !value.is_expired() => return Ok(()),
+        CacheStatus::Cached { key, value } => (key, Some(value)),
+        CacheStatus::Missed { key } => (key, None),
+    };
+
+    let block_range = block_range(chain_state, cache_backend, handler, params, &result);
+    let (is_cacheable, value) =
+        handler.extract_cache_value(result, cache_backend.get_reorg_ttl())?;
+    if !is_cacheable {
+        return Ok(());
+    }
+
+    write_cache_entry(
+        &chain_state.head,
+        cache_backend,
+        &key,
+        value,
+        &expired_value,
+        block_range,
+    )
+}
+
 fn extract_single_request_info(
     mut raw_request: Value,
 ) -> Result<(RequestId, String, Value), (Option<RequestId>, DefinedError)> {
@@ -388,27 +656,111 @@ async fn main() -> std::io::Result<()> {
     env_logger::init_from_env(Env::default().default_filter_or("info"));
 
     let args = Args::parse();
+    let config = Config::load(&args).expect("fail to load config");
 
     let mut app_state = AppState {
         chains: Default::default(),
-        http_client: reqwest::Client::new(),
+        http_client: reqwest::Client::builder()
+            .connect_timeout(Duration::from_millis(args.connect_timeout_ms))
+            .timeout(Duration::from_millis(args.request_timeout_ms))
+            .build()
+            .expect("fail to build http client"),
         metrics: metrics::Metrics::new("cached_eth_rpc"),
     };
 
     let handler_factories = rpc_cache_handler::factories();
 
-    for (name, rpc_url) in args.endpoints.iter() {
-        tracing::info!("Linked `{name}` to endpoint {rpc_url}");
+    let retry_policy = RetryPolicy {
+        max_retries: args.max_retries,
+        base_delay: Duration::from_millis(args.retry_base_delay_ms),
+        max_delay: Duration::from_millis(args.retry_max_delay_ms),
+        budget: Duration::from_millis(args.retry_budget_ms),
+    };
+
+    for (name, chain_config) in config.chains {
+        let upstreams = UpstreamPool::new(
+            name.clone(),
+            chain_config.upstreams,
+            chain_config.routes,
+            PoolOptions {
+                policy: chain_config.balance.unwrap_or(args.balance_policy),
+                retry: retry_policy.clone(),
+                timeouts: Timeouts::new(
+                    Duration::from_millis(args.request_timeout_ms),
+                    chain_config.method_timeouts,
+                ),
+                hedge: chain_config
+                    .hedge
+                    .or_else(|| args.hedge.then(HedgeConfig::default)),
+                quorum: chain_config.quorum,
+                breaker: (args.circuit_breaker_threshold > 0).then(|| BreakerPolicy {
+                    threshold: args.circuit_breaker_threshold,
+                    window: Duration::from_millis(args.circuit_breaker_window_ms),
+                    open_duration: Duration::from_millis(args.circuit_breaker_open_ms),
+                }),
+            },
+        )
+        .map(Arc::new)
+        .expect("fail to create upstream pool");
+
+        for upstream in upstreams.upstreams() {
+            tracing::info!(
+                "Linked `{name}` to endpoint {}",
+                utils::redact_url(&upstream.url)
+            );
+        }
 
-        let chain_id = utils::get_chain_id(&reqwest::Client::new(), rpc_url.as_str())
+        let chain_id = upstreams
+            .chain_id(&app_state.http_client)
             .await
             .expect("fail to get chain id");
 
-        let cache_factory = new_cache_backend_factory(&args, chain_id)
-            .expect("fail to create cache backend factory");
+        let cache_factory: Arc<dyn CacheBackendFactory> =
+            new_cache_backend_factory(&args, chain_id)
+                .expect("fail to create cache backend factory")
+                .into();
+
+        if args.health_check_interval > 0 {
+            upstream::health::spawn(
+                upstreams.clone(),
+                app_state.metrics.clone(),
+                Duration::from_secs(args.health_check_interval),
+                args.max_head_lag,
+            );
+        }
+
+        let batcher = (args.batch_window_ms > 0).then(|| {
+            Batcher::new(
+                name.clone(),
+                Duration::from_millis(args.batch_window_ms),
+                upstreams.clone(),
+                app_state.http_client.clone(),
+                app_state.metrics.clone(),
+            )
+        });
+
+        let head = match args.head_poll_interval_ms {
+            0 => HeadTracker::default(),
+            interval => HeadTracker::new(Duration::from_millis(interval)),
+        };
+        let head = Arc::new(head);
+        if args.head_poll_interval_ms > 0 {
+            head_tracker::spawn(
+                head.clone(),
+                upstreams.clone(),
+                cache_factory.clone(),
+                app_state.http_client.clone(),
+                app_state.metrics.clone(),
+                Duration::from_millis(args.head_poll_interval_ms),
+            );
+        }
 
         let mut chain_state = ChainState {
-            rpc_url: rpc_url.clone(),
+            upstreams,
+            head,
+            batcher,
+            logs: (args.logs_chunk_size > 0).then(|| LogChunks::new(args.logs_chunk_size)),
+            inflight: Coalescer::default(),
             handlers: Default::default(),
             cache_factory,
             allowed_prefixes: vec![
@@ -416,6 +768,7 @@ async fn main() -> std::io::Result<()> {
                 "alchemy_".to_string(),
                 "net_".to_string(),
                 "debug_".to_string(),
+                "trace_".to_string(),
             ],
         };
 
@@ -427,7 +780,7 @@ async fn main() -> std::io::Result<()> {
             );
         }
 
-        app_state.chains.insert(name.to_string(), chain_state);
+        app_state.chains.insert(name, chain_state);
     }
 
     let app_state = web::Data::new(app_state);
@@ -504,12 +857,69 @@ fn new_cache_backend_factory(
 }
 
 struct ChainState {
-    rpc_url: Url,
-    cache_factory: Box<dyn CacheBackendFactory>,
+    upstreams: Arc<UpstreamPool>,
+    head: Arc<HeadTracker>,
+    batcher: Option<Batcher>,
+    logs: Option<LogChunks>,
+    inflight: Coalescer,
+    cache_factory: Arc<dyn CacheBackendFactory>,
     handlers: HashMap<String, HandlerEntry>,
     allowed_prefixes: Vec<String>,
 }
 
+impl ChainState {
+    /// Sends requests upstream, `eth_getLogs` ranges are stitched from cached chunks when enabled.
+    async fn send(
+        &self,
+        client: &reqwest::Client,
+        metrics: &metrics::Metrics,
+        requests: Vec<RpcRequest>,
+    ) -> Responses {
+        let Some(logs) = &self.logs else {
+            return self.send_upstream(client, metrics, requests).await;
+        };
+
+        let (ranges, requests): (Vec<_>, Vec<_>) = requests
+            .into_iter()
+            .partition(|request| logs.matches(request));
+        let (mut responses, ranges) = futures::join!(
+            self.send_upstream(client, metrics, requests),
+            future::join_all(
+                ranges
+                    .iter()
+                    .map(|request| logs.fetch(self, client, metrics, request))
+            ),
+        );
+
+        for (value, confirmed) in ranges {
+            if !confirmed {
+                if let Ok(id) = RequestId::try_from(value["id"].clone()) {
+                    responses.unconfirmed.insert(id);
+                }
+            }
+            responses.values.push(value);
+        }
+        responses
+    }
+
+    /// Sends requests upstream, merged with those of concurrent calls when batching is enabled.
+    async fn send_upstream(
+        &self,
+        client: &reqwest::Client,
+        metrics: &metrics::Metrics,
+        requests: Vec<RpcRequest>,
+    ) -> Responses {
+        if requests.is_empty() {
+            return Responses::default();
+        }
+
+        match &self.batcher {
+            Some(batcher) => batcher.send(requests).await,
+            None => self.upstreams.send(client, metrics, &requests).await,
+        }
+    }
+}
+
 struct HandlerEntry {
     inner: Box<dyn RpcCacheHandler>,
 }
@@ -519,6 +929,44 @@ impl HandlerEntry {
         self.inner.extract_cache_key(params)
     }
 
+    fn resolve_block_tags(&self, params: &mut Value, head: &HeadTracker) {
+        self.inner.resolve_block_tags(params, head)
+    }
+
+    fn answer_from_head(&self, params: &Value, head: &HeadTracker) -> Option<Value> {
+        self.inner.answer_from_head(params, head)
+    }
+
+    fn extract_block_range(&self, params: &Value, result: &Value) -> Option<(u64, u64)> {
+        self.inner.extract_block_range(params, result)
+    }
+
+    fn locate_block_range(
+        &self,
+        params: &Value,
+        head: &HeadTracker,
+        lookup: &mut dyn FnMut(&str, Value) -> Option<Value>,
+    ) -> Option<(u64, u64)> {
+        self.inner.locate_block_range(params, head, lookup)
+    }
+
+    fn derived_entries(
+        &self,
+        params: &Value,
+        result: &Value,
+        head: &HeadTracker,
+    ) -> Vec<(&'static str, Value, Value)> {
+        self.inner.derived_entries(params, result, head)
+    }
+
+    fn assemble_from_cache(
+        &self,
+        params: &Value,
+        lookup: &mut dyn FnMut(&str, Value) -> Option<Value>,
+    ) -> Option<Value> {
+        self.inner.assemble_from_cache(params, lookup)
+    }
+
     fn extract_cache_value(
         &self,
         result: Value,
@@ -575,3 +1023,63 @@ impl Serialize for RpcRequest {
         .serialize(serializer)
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn handler(method: &str) -> Box<dyn RpcCacheHandler> {
+        rpc_cache_handler::factories()
+            .into_iter()
+            .map(|factory| factory())
+            .find(|handler| handler.method_name() == method)
+            .unwrap()
+    }
+
+    #[test]
+    fn test_settled_by_hash() {
+        let head = HeadTracker::at(1000, None, Some(990));
+        let mut cache_backend = memory_backend::MemoryBackendFactory::new(12)
+            .get_instance()
+            .unwrap();
+
+        let hash = format!("{:#066x}", 900);
+        let tx_hash = format!("{:#066x}", 0xa);
+        let block = json!({"number": "0x384", "hash": hash});
+        let tx = json!({"hash": tx_hash, "blockNumber": "0x384", "blockHash": hash});
+        let requests = [
+            ("eth_getBlockByHash", json!([hash, false]), block.clone()),
+            ("eth_getLogs", json!([{"blockHash": hash}]), json!([])),
+            ("debug_traceBlockByHash", json!([hash]), json!([])),
+            ("debug_traceTransaction", json!([tx_hash]), json!({})),
+        ];
+
+        for (method, params, result) in requests {
+            let handler = handler(method);
+            let mut lookup = |method: &str, _| match method {
+                "eth_getBlockByHash" => Some(block.clone()),
+                "eth_getTransactionReceipt" => Some(tx.clone()),
+                _ => None,
+            };
+            let block_range = handler
+                .extract_block_range(&params, &result)
+                .or_else(|| handler.locate_block_range(&params, &head, &mut lookup));
+            assert_eq!(block_range, Some((900, 900)), "{method}");
+
+            let params_key = handler.extract_cache_key(&params).unwrap().unwrap();
+            let CacheStatus::Missed { key } = cache_backend.read(method, &params_key).unwrap()
+            else {
+                panic!("{method} already cached");
+            };
+            let (_, value) = handler.extract_cache_value(result, 12).unwrap();
+            write_cache_entry(&head, &mut *cache_backend, &key, value, &None, block_range).unwrap();
+
+            let CacheStatus::Cached { value, .. } =
+                cache_backend.read(method, &params_key).unwrap()
+            else {
+                panic!("{method} not cached");
+            };
+            assert!(value.is_settled(), "{method}");
+        }
+    }
+}
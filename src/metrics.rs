@@ -1,6 +1,9 @@
 use actix_web::{web, Error, HttpResponse};
-use prometheus::{Counter, Encoder, Registry, TextEncoder, IntCounterVec};
+use prometheus::{
+    Counter, Encoder, GaugeVec, HistogramVec, IntCounterVec, IntGaugeVec, Registry, TextEncoder,
+};
 
+#[derive(Clone)]
 pub struct Metrics {
     pub registry: prometheus::Registry,
     pub cache_hit_counter: Counter,
@@ -9,6 +12,26 @@ pub struct Metrics {
     pub cache_uncacheable_counter: Counter,
     pub error_counter: Counter,
     pub method_call_counter: IntCounterVec,
+    pub coalesced_counter: IntCounterVec,
+    pub head_block_gauge: IntGaugeVec,
+    pub reorg_counter: IntCounterVec,
+    pub reorg_invalidated_counter: IntCounterVec,
+    pub batch_window_counter: IntCounterVec,
+    pub batch_window_calls_counter: IntCounterVec,
+    pub logs_chunk_counter: IntCounterVec,
+    pub logs_split_counter: IntCounterVec,
+    pub upstream_selected_counter: IntCounterVec,
+    pub upstream_retry_counter: IntCounterVec,
+    pub upstream_latency_histogram: HistogramVec,
+    pub upstream_latency_ewma_gauge: GaugeVec,
+    pub upstream_healthy_gauge: IntGaugeVec,
+    pub upstream_head_block_gauge: IntGaugeVec,
+    pub upstream_throttled_counter: IntCounterVec,
+    pub upstream_rate_limit_queue_gauge: IntGaugeVec,
+    pub upstream_hedge_counter: IntCounterVec,
+    pub upstream_quorum_counter: IntCounterVec,
+    pub upstream_circuit_state_gauge: IntGaugeVec,
+    pub upstream_circuit_transition_counter: IntCounterVec,
 }
 
 // Function to add a prefix to the metric names
@@ -45,6 +68,51 @@ fn register_int_counter_vec_with_prefix(
     counter_vec
 }
 
+// Create a function to register HistogramVec with a prefix
+fn register_histogram_vec_with_prefix(
+    registry: &Registry,
+    prefix: &str,
+    name: &str,
+    description: &str,
+    labels: &[&str],
+) -> HistogramVec {
+    let name = add_prefix(prefix, name);
+    let opts = prometheus::HistogramOpts::new(name, description);
+    let histogram_vec = HistogramVec::new(opts, labels).unwrap();
+    registry.register(Box::new(histogram_vec.clone())).unwrap();
+    histogram_vec
+}
+
+// Create a function to register GaugeVec with a prefix
+fn register_gauge_vec_with_prefix(
+    registry: &Registry,
+    prefix: &str,
+    name: &str,
+    description: &str,
+    labels: &[&str],
+) -> GaugeVec {
+    let name = add_prefix(prefix, name);
+    let opts = prometheus::Opts::new(name, description);
+    let gauge_vec = GaugeVec::new(opts, labels).unwrap();
+    registry.register(Box::new(gauge_vec.clone())).unwrap();
+    gauge_vec
+}
+
+// Create a function to register IntGaugeVec with a prefix
+fn register_int_gauge_vec_with_prefix(
+    registry: &Registry,
+    prefix: &str,
+    name: &str,
+    description: &str,
+    labels: &[&str],
+) -> IntGaugeVec {
+    let name = add_prefix(prefix, name);
+    let opts = prometheus::Opts::new(name, description);
+    let gauge_vec = IntGaugeVec::new(opts, labels).unwrap();
+    registry.register(Box::new(gauge_vec.clone())).unwrap();
+    gauge_vec
+}
+
 impl Metrics {
     pub fn new(prefix: &str) -> Self {
         let registry = Registry::new();
@@ -86,6 +154,146 @@ impl Metrics {
             "Total number of method calls per chain",
             &["chain", "method", "cache"],
         );
+        let upstream_selected_counter = register_int_counter_vec_with_prefix(
+            &registry,
+            prefix,
+            "upstream_selected_total",
+            "Total number of batches sent to each upstream",
+            &["chain", "upstream"],
+        );
+        let upstream_retry_counter = register_int_counter_vec_with_prefix(
+            &registry,
+            prefix,
+            "upstream_retry_total",
+            "Total number of requests retried after a transient upstream error",
+            &["chain"],
+        );
+        let upstream_latency_histogram = register_histogram_vec_with_prefix(
+            &registry,
+            prefix,
+            "upstream_latency_seconds",
+            "Latency of successful requests to each upstream",
+            &["chain", "upstream"],
+        );
+        let upstream_latency_ewma_gauge = register_gauge_vec_with_prefix(
+            &registry,
+            prefix,
+            "upstream_latency_ewma_seconds",
+            "Moving average of the latency of each upstream, as seen by the load balancer",
+            &["chain", "upstream"],
+        );
+        let upstream_healthy_gauge = register_int_gauge_vec_with_prefix(
+            &registry,
+            prefix,
+            "upstream_healthy",
+            "Whether the upstream is in rotation according to the health check",
+            &["chain", "upstream"],
+        );
+        let upstream_head_block_gauge = register_int_gauge_vec_with_prefix(
+            &registry,
+            prefix,
+            "upstream_head_block",
+            "Latest block number reported by the upstream health check",
+            &["chain", "upstream"],
+        );
+        let upstream_throttled_counter = register_int_counter_vec_with_prefix(
+            &registry,
+            prefix,
+            "upstream_throttled_total",
+            "Total number of batches over the rate limit of an upstream, by outcome (failover, queued, rejected)",
+            &["chain", "upstream", "outcome"],
+        );
+        let upstream_rate_limit_queue_gauge = register_int_gauge_vec_with_prefix(
+            &registry,
+            prefix,
+            "upstream_rate_limit_queue",
+            "Number of batches waiting for rate limit capacity of an upstream",
+            &["chain", "upstream"],
+        );
+        let upstream_hedge_counter = register_int_counter_vec_with_prefix(
+            &registry,
+            prefix,
+            "upstream_hedged_total",
+            "Total number of hedged batches, by the upstream which answered first (primary, hedge)",
+            &["chain", "winner"],
+        );
+        let upstream_quorum_counter = register_int_counter_vec_with_prefix(
+            &registry,
+            prefix,
+            "upstream_quorum_total",
+            "Total number of quorum reads, by outcome (confirmed, majority, failed)",
+            &["chain", "outcome"],
+        );
+        let upstream_circuit_state_gauge = register_int_gauge_vec_with_prefix(
+            &registry,
+            prefix,
+            "upstream_circuit_state",
+            "State of the circuit breaker of each upstream (0 closed, 1 half-open, 2 open)",
+            &["chain", "upstream"],
+        );
+        let upstream_circuit_transition_counter = register_int_counter_vec_with_prefix(
+            &registry,
+            prefix,
+            "upstream_circuit_transitions_total",
+            "Total number of circuit breaker transitions, by new state",
+            &["chain", "upstream", "state"],
+        );
+        let coalesced_counter = register_int_counter_vec_with_prefix(
+            &registry,
+            prefix,
+            "coalesced_total",
+            "Total number of cache misses answered by an identical call already in flight",
+            &["chain", "method"],
+        );
+        let batch_window_counter = register_int_counter_vec_with_prefix(
+            &registry,
+            prefix,
+            "batch_window_total",
+            "Total number of upstream batches merged from concurrent calls",
+            &["chain"],
+        );
+        let batch_window_calls_counter = register_int_counter_vec_with_prefix(
+            &registry,
+            prefix,
+            "batch_window_calls_total",
+            "Total number of calls merged into upstream batches",
+            &["chain"],
+        );
+        let logs_chunk_counter = register_int_counter_vec_with_prefix(
+            &registry,
+            prefix,
+            "logs_chunk_total",
+            "Total number of eth_getLogs chunks read from cache or fetched upstream",
+            &["chain", "cache"],
+        );
+        let logs_split_counter = register_int_counter_vec_with_prefix(
+            &registry,
+            prefix,
+            "logs_split_total",
+            "Total number of eth_getLogs ranges split because the upstream found them too large",
+            &["chain"],
+        );
+        let head_block_gauge = register_int_gauge_vec_with_prefix(
+            &registry,
+            prefix,
+            "head_block",
+            "Number of the latest, safe and finalized blocks of each chain",
+            &["chain", "tag"],
+        );
+        let reorg_counter = register_int_counter_vec_with_prefix(
+            &registry,
+            prefix,
+            "reorg_total",
+            "Total number of chain reorganizations noticed by the head tracker",
+            &["chain"],
+        );
+        let reorg_invalidated_counter = register_int_counter_vec_with_prefix(
+            &registry,
+            prefix,
+            "reorg_invalidated_total",
+            "Total number of cache entries dropped because their block was reorganized",
+            &["chain"],
+        );
 
         Self {
             registry,
@@ -95,6 +303,26 @@ impl Metrics {
             cache_uncacheable_counter,
             error_counter,
             method_call_counter,
+            coalesced_counter,
+            head_block_gauge,
+            reorg_counter,
+            reorg_invalidated_counter,
+            batch_window_counter,
+            batch_window_calls_counter,
+            logs_chunk_counter,
+            logs_split_counter,
+            upstream_selected_counter,
+            upstream_retry_counter,
+            upstream_latency_histogram,
+            upstream_latency_ewma_gauge,
+            upstream_healthy_gauge,
+            upstream_head_block_gauge,
+            upstream_throttled_counter,
+            upstream_rate_limit_queue_gauge,
+            upstream_hedge_counter,
+            upstream_quorum_counter,
+            upstream_circuit_state_gauge,
+            upstream_circuit_transition_counter,
         }
     }
 }
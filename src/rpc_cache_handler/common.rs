@@ -1,9 +1,10 @@
 use std::str::FromStr;
 
 use crate::cache::CacheValue;
+use crate::head_tracker::HeadTracker;
 use alloy_primitives::{Address, B256, U64};
 use anyhow::{bail, Context};
-use serde_json::Value;
+use serde_json::{json, Value};
 use sha1::Digest;
 
 pub enum ParamsSpec {
@@ -96,6 +97,171 @@ pub fn extract_and_format_block_tag(value: &Value) -> anyhow::Result<Option<Stri
     }
 }
 
+/// Number of a block given as a hex quantity, `None` for tags and hashes.
+pub fn parse_block_number(value: &Value) -> Option<u64> {
+    let value = value.as_str()?;
+    if value.len() == 66 {
+        return None;
+    }
+
+    U64::from_str(value).ok().map(|number| number.as_limbs()[0])
+}
+
+/// Block range of a request pinned to the block number given by a param.
+pub fn block_param_range(value: Option<&Value>) -> Option<(u64, u64)> {
+    let number = parse_block_number(value?)?;
+    Some((number, number))
+}
+
+/// Rewrites a `latest`, `safe` or `finalized` block tag to the number it currently points at.
+pub fn resolve_block_tag(value: Option<&mut Value>, head: &HeadTracker) {
+    let Some(value) = value else {
+        return;
+    };
+
+    if let Some(number) = value.as_str().and_then(|tag| head.resolve(tag)) {
+        *value = Value::String(format!("0x{number:x}"));
+    }
+}
+
+/// Whether the result tells of a block which isn't the canonical one known to the head at its
+/// number: the block itself, or the block of each log, receipt, transaction or trace.
+pub fn is_orphaned(result: &Value, head: &HeadTracker) -> bool {
+    let (number, hash) = match result {
+        Value::Array(items) => return items.iter().any(|item| is_orphaned(item, head)),
+        Value::Object(block) if block.contains_key("parentHash") => {
+            (&result["number"], &result["hash"])
+        }
+        Value::Object(_) => (&result["blockNumber"], &result["blockHash"]),
+        _ => return false,
+    };
+
+    // traces tell the number as an integer
+    let number = number.as_u64().or_else(|| parse_block_number(number));
+    match (number, hash.as_str()) {
+        (Some(number), Some(hash)) => head
+            .canonical_hash(number)
+            .is_some_and(|canonical| !canonical.eq_ignore_ascii_case(hash)),
+        _ => false,
+    }
+}
+
+/// Entry of the same trace under the other identifier of its block, for `method` the trace by
+/// that identifier. Traces don't tell their block, the head has to know it.
+pub fn derive_trace_alias(
+    method: &'static str,
+    params: &Value,
+    result: &Value,
+    head: &HeadTracker,
+) -> Vec<(&'static str, Value, Value)> {
+    block_alias_params(params, None, None, head)
+        .map(|params| (method, params, result.clone()))
+        .into_iter()
+        .collect()
+}
+
+/// Params of the same request with the block given by its other identifier: the hash of a block
+/// given by number, or the number of a block given by hash. `number` and `hash` are taken from
+/// the result when it has them, else from the head. A hash is only mapped to a number when it is
+/// the canonical block known to the head, an orphaned block must not stand for the number.
+pub fn block_alias_params(
+    params: &Value,
+    number: Option<u64>,
+    hash: Option<&str>,
+    head: &HeadTracker,
+) -> Option<Value> {
+    let block = params.get(0)?;
+    let alias = match parse_block_number(block) {
+        Some(requested) => match hash {
+            Some(hash) => extract_and_format_block_hash(&json!(hash)).ok()?,
+            None => head.canonical_hash(requested)?,
+        },
+        None => {
+            let requested = extract_and_format_block_hash(block).ok()?;
+            let number = number.or_else(|| head.canonical_number(&requested))?;
+            if head.canonical_hash(number)? != requested {
+                return None;
+            }
+            format!("0x{number:x}")
+        }
+    };
+
+    let mut params = params.clone();
+    params[0] = Value::String(alias);
+    Some(params)
+}
+
+/// Entries derived from a block: the block by its other identifier and, for a block with full
+/// transactions, the hash-only block and each transaction by hash and by position. Entries by
+/// number, and transactions by hash, are only derived from a canonical block.
+pub fn derive_block_entries(
+    params: &Value,
+    result: &Value,
+    head: &HeadTracker,
+) -> Vec<(&'static str, Value, Value)> {
+    let Some(block) = params.get(0) else {
+        return vec![];
+    };
+    if !result.is_object() {
+        return vec![];
+    }
+
+    let number = parse_block_number(&result["number"]);
+    let alias = block_alias_params(params, number, result["hash"].as_str(), head);
+    let (by_number, by_hash) = match parse_block_number(block) {
+        Some(_) => (Some(params.clone()), alias),
+        None => (alias, Some(params.clone())),
+    };
+
+    let transactions = match (params.get(1), result["transactions"].as_array()) {
+        (Some(Value::Bool(true)), Some(transactions)) => transactions.as_slice(),
+        _ => &[],
+    };
+    let mut hashes_only = result.clone();
+    hashes_only["transactions"] = transactions.iter().map(|tx| tx["hash"].clone()).collect();
+
+    let mut entries = vec![];
+    for (block_method, transaction_method, params) in [
+        (
+            "eth_getBlockByNumber",
+            "eth_getTransactionByBlockNumberAndIndex",
+            &by_number,
+        ),
+        (
+            "eth_getBlockByHash",
+            "eth_getTransactionByBlockHashAndIndex",
+            &by_hash,
+        ),
+    ] {
+        let Some(params) = params else {
+            continue;
+        };
+
+        entries.push((block_method, params.clone(), result.clone()));
+        if params.get(1) != Some(&Value::Bool(true)) {
+            continue;
+        }
+
+        let block = &params[0];
+        entries.push((block_method, json!([block, false]), hashes_only.clone()));
+        for (index, tx) in transactions.iter().enumerate() {
+            let index = format!("0x{index:x}");
+            entries.push((transaction_method, json!([block, index]), tx.clone()));
+        }
+    }
+
+    // a transaction by hash is the one of the canonical chain
+    if by_number.is_some() {
+        for tx in transactions {
+            entries.push(("eth_getTransactionByHash", json!([tx["hash"]]), tx.clone()));
+        }
+    }
+
+    // the entry of the request itself is written already
+    entries.retain(|(_, derived, _)| derived != params);
+    entries
+}
+
 pub fn hash_string(s: &str) -> String {
     let mut hasher = sha1::Sha1::new();
     hasher.update(s.as_bytes());
@@ -106,6 +272,138 @@ pub fn hash_string(s: &str) -> String {
 
 #[cfg(test)]
 mod test {
+    mod test_parse_block_number {
+        use super::super::*;
+        use serde_json::json;
+
+        #[test]
+        fn test_parse() {
+            assert_eq!(parse_block_number(&json!("0x1b4")), Some(436));
+            assert_eq!(parse_block_number(&json!("latest")), None);
+            assert_eq!(parse_block_number(&json!(null)), None);
+            assert_eq!(
+                parse_block_number(&json!(
+                    "0x1234567890abcdef1234567890abcdef1234567890abcdef1234567890abcdef"
+                )),
+                None
+            );
+        }
+    }
+
+    mod test_is_orphaned {
+        use super::super::*;
+        use serde_json::json;
+
+        #[test]
+        fn test_orphaned() {
+            let head = HeadTracker::at(0x100, None, None);
+            let canonical = format!("{:#066x}", 0x100);
+            let orphaned = format!("{:#066x}", 0xdead);
+
+            let block = |hash: &str| json!({"number": "0x100", "hash": hash, "parentHash": "0x0"});
+            assert!(!is_orphaned(&block(&canonical), &head));
+            assert!(is_orphaned(&block(&orphaned), &head));
+
+            let logs = json!([
+                {"blockNumber": "0x100", "blockHash": canonical},
+                {"blockNumber": "0x100", "blockHash": orphaned},
+            ]);
+            assert!(is_orphaned(&logs, &head));
+
+            // unknown to the head
+            let trace = json!([{"blockNumber": 0x50, "blockHash": orphaned}]);
+            assert!(!is_orphaned(&trace, &head));
+            assert!(!is_orphaned(&json!("0x1"), &head));
+        }
+    }
+
+    mod test_block_alias_params {
+        use super::super::*;
+        use serde_json::json;
+
+        #[test]
+        fn test_by_number() {
+            let head = HeadTracker::at(0x100, None, None);
+            let hash = format!("{:#066x}", 0x100);
+
+            // the hash of the result, else the one of the head
+            let params = json!(["0xff", true]);
+            assert_eq!(
+                block_alias_params(&params, None, Some(&hash), &head),
+                Some(json!([hash, true]))
+            );
+            assert_eq!(
+                block_alias_params(&json!(["0x100"]), None, None, &head),
+                Some(json!([hash]))
+            );
+            assert_eq!(
+                block_alias_params(&json!(["0xff"]), None, None, &head),
+                None
+            );
+            assert_eq!(
+                block_alias_params(&json!(["latest"]), None, Some(&hash), &head),
+                None
+            );
+        }
+
+        #[test]
+        fn test_by_hash() {
+            let head = HeadTracker::at(0x100, None, None);
+            let hash = format!("{:#066x}", 0x100);
+
+            let params = json!([hash, false]);
+            assert_eq!(
+                block_alias_params(&params, Some(0x100), None, &head),
+                Some(json!(["0x100", false]))
+            );
+            assert_eq!(
+                block_alias_params(&params, None, None, &head),
+                Some(json!(["0x100", false]))
+            );
+
+            // not the canonical block of that number
+            let orphan = json!([format!("{:#066x}", 0xabc), false]);
+            assert_eq!(block_alias_params(&orphan, Some(0x100), None, &head), None);
+            assert_eq!(block_alias_params(&orphan, None, None, &head), None);
+        }
+    }
+
+    mod test_resolve_block_tag {
+        use super::super::*;
+        use serde_json::json;
+
+        #[test]
+        fn test_resolve() {
+            let head = HeadTracker::at(0x100, Some(0xfa), Some(0xf0));
+
+            for (tag, resolved) in [
+                ("latest", "0x100"),
+                ("safe", "0xfa"),
+                ("finalized", "0xf0"),
+                ("pending", "pending"),
+                ("earliest", "earliest"),
+                ("0x12", "0x12"),
+            ] {
+                let mut params = json!([tag]);
+                resolve_block_tag(params.get_mut(0), &head);
+                assert_eq!(params, json!([resolved]));
+            }
+
+            let mut params = json!([]);
+            resolve_block_tag(params.get_mut(0), &head);
+            assert_eq!(params, json!([]));
+        }
+
+        #[test]
+        fn test_unknown_head() {
+            let head = HeadTracker::at(0x100, None, None);
+
+            let mut params = json!(["safe"]);
+            resolve_block_tag(params.get_mut(0), &head);
+            assert_eq!(params, json!(["safe"]));
+        }
+    }
+
     mod test_extract_and_format_block_tag {
         use super::super::*;
         use serde_json::json;
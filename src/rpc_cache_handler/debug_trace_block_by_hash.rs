@@ -1,6 +1,7 @@
 use anyhow::Context;
-use serde_json::Value;
+use serde_json::{json, Value};
 
+use crate::head_tracker::HeadTracker;
 use crate::rpc_cache_handler::common::ParamsSpec;
 use crate::rpc_cache_handler::{common, RpcCacheHandler};
 
@@ -29,12 +30,35 @@ impl RpcCacheHandler for Handler {
             Ok(Some(block_hash))
         }
     }
+
+    // the block is known to the head while recent, or from the cached block of the hash
+    fn locate_block_range(
+        &self,
+        params: &Value,
+        head: &HeadTracker,
+        lookup: &mut dyn FnMut(&str, Value) -> Option<Value>,
+    ) -> Option<(u64, u64)> {
+        let hash = common::extract_and_format_block_hash(params.get(0)?).ok()?;
+        let number = head.canonical_number(&hash).or_else(|| {
+            let block = lookup("eth_getBlockByHash", json!([hash, false]))?;
+            common::parse_block_number(&block["number"])
+        })?;
+        Some((number, number))
+    }
+
+    fn derived_entries(
+        &self,
+        params: &Value,
+        result: &Value,
+        head: &HeadTracker,
+    ) -> Vec<(&'static str, Value, Value)> {
+        common::derive_trace_alias("debug_traceBlockByNumber", params, result, head)
+    }
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
-    use serde_json::json;
 
     static HANDLER: Handler = Handler;
 
@@ -70,6 +94,26 @@ mod test {
         );
     }
 
+    #[test]
+    fn test_locate_block_range() {
+        let hash = format!("{:#066x}", 0x1234);
+        let params = json!([hash, {"tracer": "callTracer"}]);
+
+        let head = HeadTracker::at(0x1234, None, None);
+        let range = HANDLER.locate_block_range(&params, &head, &mut |_, _| None);
+        assert_eq!(range, Some((0x1234, 0x1234)));
+
+        let range =
+            HANDLER.locate_block_range(&params, &HeadTracker::default(), &mut |method, _| {
+                assert_eq!(method, "eth_getBlockByHash");
+                Some(json!({"number": "0x1234", "hash": hash}))
+            });
+        assert_eq!(range, Some((0x1234, 0x1234)));
+
+        let range = HANDLER.locate_block_range(&params, &HeadTracker::default(), &mut |_, _| None);
+        assert_eq!(range, None);
+    }
+
     #[test]
     fn test_invalid_block_hash() {
         let params = json!(["0x1234567890abcdef1234567890abcdef1234567890abcdef123456789ggggggg"]);
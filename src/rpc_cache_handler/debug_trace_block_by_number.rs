@@ -1,6 +1,7 @@
 use anyhow::Context;
 use serde_json::Value;
 
+use crate::head_tracker::HeadTracker;
 use crate::rpc_cache_handler::common::ParamsSpec;
 use crate::rpc_cache_handler::{common, RpcCacheHandler};
 
@@ -33,6 +34,23 @@ impl RpcCacheHandler for Handler {
             Ok(Some(block_number))
         }
     }
+
+    fn resolve_block_tags(&self, params: &mut Value, head: &HeadTracker) {
+        common::resolve_block_tag(params.get_mut(0), head);
+    }
+
+    fn extract_block_range(&self, params: &Value, _result: &Value) -> Option<(u64, u64)> {
+        common::block_param_range(params.get(0))
+    }
+
+    fn derived_entries(
+        &self,
+        params: &Value,
+        result: &Value,
+        head: &HeadTracker,
+    ) -> Vec<(&'static str, Value, Value)> {
+        common::derive_trace_alias("debug_traceBlockByHash", params, result, head)
+    }
 }
 
 #[cfg(test)]
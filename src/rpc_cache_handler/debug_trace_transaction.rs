@@ -1,7 +1,8 @@
 use alloy_primitives::B256;
 use anyhow::Context;
-use serde_json::Value;
+use serde_json::{json, Value};
 
+use crate::head_tracker::HeadTracker;
 use crate::rpc_cache_handler::{common, RpcCacheHandler};
 
 #[derive(Default, Clone)]
@@ -29,12 +30,28 @@ impl RpcCacheHandler for Handler {
             Ok(Some(format!("{tx_hash:#x}")))
         }
     }
+
+    // traces don't tell their block, the cached receipt or transaction does
+    fn locate_block_range(
+        &self,
+        params: &Value,
+        _head: &HeadTracker,
+        lookup: &mut dyn FnMut(&str, Value) -> Option<Value>,
+    ) -> Option<(u64, u64)> {
+        let tx_hash = params.get(0)?;
+        let number = ["eth_getTransactionReceipt", "eth_getTransactionByHash"]
+            .into_iter()
+            .find_map(|method| {
+                let result = lookup(method, json!([tx_hash]))?;
+                common::parse_block_number(&result["blockNumber"])
+            })?;
+        Some((number, number))
+    }
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
-    use serde_json::json;
 
     static HANDLER: Handler = Handler;
 
@@ -59,6 +76,30 @@ mod test {
         );
     }
 
+    #[test]
+    fn test_locate_block_range() {
+        let params = json!(["0x1234567890abcdef1234567890abcdef1234567890abcdef1234567890abcdef"]);
+        let head = HeadTracker::default();
+
+        let mut methods = vec![];
+        let range = HANDLER.locate_block_range(&params, &head, &mut |method, params| {
+            assert_eq!(
+                params,
+                json!(["0x1234567890abcdef1234567890abcdef1234567890abcdef1234567890abcdef"])
+            );
+            methods.push(method.to_string());
+            (method == "eth_getTransactionByHash").then(|| json!({"blockNumber": "0x1234"}))
+        });
+        assert_eq!(range, Some((0x1234, 0x1234)));
+        assert_eq!(
+            methods,
+            vec!["eth_getTransactionReceipt", "eth_getTransactionByHash"]
+        );
+
+        let range = HANDLER.locate_block_range(&params, &head, &mut |_, _| None);
+        assert_eq!(range, None);
+    }
+
     #[test]
     fn test_invalid_tx() {
         let params = json!(["0xgg"]);
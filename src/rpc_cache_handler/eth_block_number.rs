@@ -1,6 +1,7 @@
 use crate::rpc_cache_handler::RpcCacheHandler;
 use serde_json::Value;
 
+/// Also written by the head tracker on every poll, see `head_tracker::cache_block_number`.
 #[derive(Default, Clone)]
 pub struct Handler {}
 
@@ -10,7 +11,7 @@ impl RpcCacheHandler for Handler {
     }
 
     fn extract_cache_key(&self, _: &Value) -> anyhow::Result<Option<String>> {
-        Ok(Some(format!("eth_blockNumber")))
+        Ok(Some("eth_blockNumber".to_string()))
     }
 
     fn get_ttl(&self) -> u32 {
@@ -1,6 +1,7 @@
 use anyhow::{bail, Context};
 use serde_json::Value;
 
+use crate::head_tracker::HeadTracker;
 use crate::rpc_cache_handler::{common, RpcCacheHandler};
 
 #[derive(Default, Clone)]
@@ -46,6 +47,14 @@ impl RpcCacheHandler for Handler {
 
         Ok(Some(format!("{block_tag}-{tx_hash}")))
     }
+
+    fn resolve_block_tags(&self, params: &mut Value, head: &HeadTracker) {
+        common::resolve_block_tag(params.get_mut(1), head);
+    }
+
+    fn extract_block_range(&self, params: &Value, _result: &Value) -> Option<(u64, u64)> {
+        common::block_param_range(params.get(1))
+    }
 }
 
 #[cfg(test)]
@@ -10,7 +10,7 @@ impl RpcCacheHandler for Handler {
     }
 
     fn extract_cache_key(&self, _: &Value) -> anyhow::Result<Option<String>> {
-        Ok(Some(format!("eth_gasPrice")))
+        Ok(Some("eth_gasPrice".to_string()))
     }
 
     fn get_ttl(&self) -> u32 {
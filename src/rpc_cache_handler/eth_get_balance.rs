@@ -1,5 +1,6 @@
 use serde_json::Value;
 
+use crate::head_tracker::HeadTracker;
 use crate::rpc_cache_handler::{common, RpcCacheHandler};
 
 #[derive(Default, Clone)]
@@ -13,4 +14,12 @@ impl RpcCacheHandler for Handler {
     fn extract_cache_key(&self, params: &Value) -> anyhow::Result<Option<String>> {
         common::extract_address_cache_key(params)
     }
+
+    fn resolve_block_tags(&self, params: &mut Value, head: &HeadTracker) {
+        common::resolve_block_tag(params.get_mut(1), head);
+    }
+
+    fn extract_block_range(&self, params: &Value, _result: &Value) -> Option<(u64, u64)> {
+        common::block_param_range(params.get(1))
+    }
 }
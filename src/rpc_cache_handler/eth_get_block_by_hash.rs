@@ -1,6 +1,7 @@
 use anyhow::Context;
 use serde_json::Value;
 
+use crate::head_tracker::HeadTracker;
 use crate::rpc_cache_handler::{common, RpcCacheHandler};
 
 #[derive(Default, Clone)]
@@ -24,6 +25,20 @@ impl RpcCacheHandler for Handler {
             Ok(Some(block_hash))
         }
     }
+
+    fn extract_block_range(&self, _params: &Value, result: &Value) -> Option<(u64, u64)> {
+        let number = common::parse_block_number(&result["number"])?;
+        Some((number, number))
+    }
+
+    fn derived_entries(
+        &self,
+        params: &Value,
+        result: &Value,
+        head: &HeadTracker,
+    ) -> Vec<(&'static str, Value, Value)> {
+        common::derive_block_entries(params, result, head)
+    }
 }
 
 #[cfg(test)]
@@ -42,6 +57,48 @@ mod test {
         );
     }
 
+    #[test]
+    fn test_block_range() {
+        let hash = format!("{:#066x}", 0x1234);
+        let result = json!({"number": "0x1234", "hash": hash});
+        assert_eq!(
+            HANDLER.extract_block_range(&json!([hash, false]), &result),
+            Some((0x1234, 0x1234))
+        );
+        assert_eq!(
+            HANDLER.extract_block_range(&json!([hash]), &Value::Null),
+            None
+        );
+    }
+
+    #[test]
+    fn test_derived_entries() {
+        let hash = format!("{:#066x}", 0x1234);
+        let tx = json!({"hash": "0xa", "blockHash": hash});
+        let result = json!({"number": "0x1234", "hash": hash, "transactions": [tx]});
+        let params = json!([hash, true]);
+
+        // only by hash while the block isn't known to be canonical
+        let entries = HANDLER.derived_entries(&params, &result, &HeadTracker::default());
+        let methods = entries
+            .iter()
+            .map(|(method, ..)| *method)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            methods,
+            vec![
+                "eth_getBlockByHash",
+                "eth_getTransactionByBlockHashAndIndex"
+            ]
+        );
+
+        let head = HeadTracker::at(0x1234, None, None);
+        let entries = HANDLER.derived_entries(&params, &result, &head);
+        assert_eq!(entries.len(), 6);
+        assert!(entries.contains(&("eth_getBlockByNumber", json!(["0x1234", true]), result)));
+        assert!(entries.contains(&("eth_getTransactionByHash", json!(["0xa"]), tx)));
+    }
+
     #[test]
     fn test_normal_case() {
         let params = json!([
@@ -1,6 +1,7 @@
 use anyhow::Context;
 use serde_json::Value;
 
+use crate::head_tracker::HeadTracker;
 use crate::rpc_cache_handler::{common, RpcCacheHandler};
 
 #[derive(Default, Clone)]
@@ -28,6 +29,37 @@ impl RpcCacheHandler for Handler {
             Ok(Some(block_tag))
         }
     }
+
+    fn resolve_block_tags(&self, params: &mut Value, head: &HeadTracker) {
+        common::resolve_block_tag(params.get_mut(0), head);
+
+        // without transactions by default, the key of the head blocks
+        if let Some(params) = params.as_array_mut().filter(|params| params.len() == 1) {
+            params.push(Value::Bool(false));
+        }
+    }
+
+    // with transactions, the pending block changes with every poll of the mempool
+    fn answer_from_head(&self, params: &Value, head: &HeadTracker) -> Option<Value> {
+        match params.as_array()?.as_slice() {
+            [Value::String(tag), Value::Bool(false)] if tag == "pending" => head.pending(),
+            _ => None,
+        }
+    }
+
+    fn extract_block_range(&self, _params: &Value, result: &Value) -> Option<(u64, u64)> {
+        let number = common::parse_block_number(&result["number"])?;
+        Some((number, number))
+    }
+
+    fn derived_entries(
+        &self,
+        params: &Value,
+        result: &Value,
+        head: &HeadTracker,
+    ) -> Vec<(&'static str, Value, Value)> {
+        common::derive_block_entries(params, result, head)
+    }
 }
 
 #[cfg(test)]
@@ -69,6 +101,90 @@ mod test {
         assert_eq!(cache_key, None);
     }
 
+    #[test]
+    fn test_resolve_block_tags() {
+        let head = HeadTracker::at(0x100, Some(0xf0), Some(0xe0));
+
+        let mut params = json!(["safe", false]);
+        HANDLER.resolve_block_tags(&mut params, &head);
+        assert_eq!(params, json!(["0xf0", false]));
+
+        let mut params = json!(["pending", false]);
+        HANDLER.resolve_block_tags(&mut params, &head);
+        assert_eq!(params, json!(["pending", false]));
+
+        // the same entry as the head block cached by the tracker
+        let mut params = json!(["latest"]);
+        HANDLER.resolve_block_tags(&mut params, &head);
+        assert_eq!(params, json!(["0x100", false]));
+        assert_eq!(
+            HANDLER.extract_cache_key(&params).unwrap().as_deref(),
+            Some("0x100-false")
+        );
+    }
+
+    #[test]
+    fn test_derived_entries() {
+        let head = HeadTracker::default();
+        let hash = format!("{:#066x}", 0x1234);
+        let result = json!({"number": "0x1234", "hash": hash, "transactions": ["0xaa"]});
+
+        let entries = HANDLER.derived_entries(&json!(["0x1234", false]), &result, &head);
+        assert_eq!(
+            entries,
+            vec![("eth_getBlockByHash", json!([hash, false]), result)]
+        );
+
+        assert!(HANDLER
+            .derived_entries(&json!(["0x1234", false]), &Value::Null, &head)
+            .is_empty());
+    }
+
+    #[test]
+    fn test_derived_from_full_block() {
+        let head = HeadTracker::default();
+        let hash = format!("{:#066x}", 0x1234);
+        let tx = |index: u64| json!({"hash": format!("0x{index:x}"), "blockHash": hash});
+        let result = json!({"number": "0x1234", "hash": hash, "transactions": [tx(10), tx(11)]});
+        let hashes_only = json!({"number": "0x1234", "hash": hash, "transactions": ["0xa", "0xb"]});
+
+        let entries = HANDLER.derived_entries(&json!(["0x1234", true]), &result, &head);
+        assert_eq!(
+            entries,
+            vec![
+                (
+                    "eth_getBlockByNumber",
+                    json!(["0x1234", false]),
+                    hashes_only.clone()
+                ),
+                (
+                    "eth_getTransactionByBlockNumberAndIndex",
+                    json!(["0x1234", "0x0"]),
+                    tx(10)
+                ),
+                (
+                    "eth_getTransactionByBlockNumberAndIndex",
+                    json!(["0x1234", "0x1"]),
+                    tx(11)
+                ),
+                ("eth_getBlockByHash", json!([hash, true]), result),
+                ("eth_getBlockByHash", json!([hash, false]), hashes_only),
+                (
+                    "eth_getTransactionByBlockHashAndIndex",
+                    json!([hash, "0x0"]),
+                    tx(10)
+                ),
+                (
+                    "eth_getTransactionByBlockHashAndIndex",
+                    json!([hash, "0x1"]),
+                    tx(11)
+                ),
+                ("eth_getTransactionByHash", json!(["0xa"]), tx(10)),
+                ("eth_getTransactionByHash", json!(["0xb"]), tx(11)),
+            ]
+        );
+    }
+
     #[test]
     fn test_invalid_transaction_detail() {
         let params = json!(["0x1234", 1]);
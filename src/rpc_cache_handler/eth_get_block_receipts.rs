@@ -1,6 +1,7 @@
 use anyhow::Context;
-use serde_json::Value;
+use serde_json::{json, Value};
 
+use crate::head_tracker::HeadTracker;
 use crate::rpc_cache_handler::{common, RpcCacheHandler};
 
 #[derive(Default, Clone)]
@@ -23,4 +24,156 @@ impl RpcCacheHandler for Handler {
 
         Ok(Some(block_tag))
     }
+
+    fn extract_block_range(&self, _params: &Value, result: &Value) -> Option<(u64, u64)> {
+        let number = common::parse_block_number(&result[0]["blockNumber"])?;
+        Some((number, number))
+    }
+
+    // the receipts of a block are the same whether it is given by number or by hash, and each
+    // one is the receipt of its transaction when the block is canonical
+    fn derived_entries(
+        &self,
+        params: &Value,
+        result: &Value,
+        head: &HeadTracker,
+    ) -> Vec<(&'static str, Value, Value)> {
+        let Some(block) = params.get(0) else {
+            return vec![];
+        };
+        let number = common::parse_block_number(&result[0]["blockNumber"]);
+        let alias =
+            common::block_alias_params(params, number, result[0]["blockHash"].as_str(), head);
+        let canonical = common::parse_block_number(block).is_some() || alias.is_some();
+
+        let mut entries = alias
+            .map(|params| (self.method_name(), params, result.clone()))
+            .into_iter()
+            .collect::<Vec<_>>();
+        if let (true, Some(receipts)) = (canonical, result.as_array()) {
+            for receipt in receipts {
+                let params = json!([receipt["transactionHash"]]);
+                entries.push(("eth_getTransactionReceipt", params, receipt.clone()));
+            }
+        }
+        entries
+    }
+
+    // the transactions of the block tell which receipts make it up
+    fn assemble_from_cache(
+        &self,
+        params: &Value,
+        lookup: &mut dyn FnMut(&str, Value) -> Option<Value>,
+    ) -> Option<Value> {
+        let block = params.get(0)?;
+        let method = match common::parse_block_number(block) {
+            Some(_) => "eth_getBlockByNumber",
+            None => "eth_getBlockByHash",
+        };
+        let block = lookup(method, json!([block, false]))?;
+        let hash = block["hash"].as_str()?.to_lowercase();
+
+        let receipts = block["transactions"]
+            .as_array()?
+            .iter()
+            .map(|transaction| {
+                let receipt = lookup("eth_getTransactionReceipt", json!([transaction]))?;
+                // the receipt of the same transaction in another fork doesn't belong here
+                let block_hash = receipt["blockHash"].as_str()?.to_lowercase();
+                (block_hash == hash).then_some(receipt)
+            })
+            .collect::<Option<Vec<_>>>()?;
+        Some(Value::Array(receipts))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+
+    use super::*;
+
+    static HANDLER: Handler = Handler;
+
+    fn receipt(block: u64, index: u64) -> Value {
+        json!({
+            "transactionHash": format!("0x{index:x}"),
+            "blockHash": format!("{block:#066x}"),
+            "blockNumber": format!("0x{block:x}"),
+        })
+    }
+
+    #[test]
+    fn test_derived_entries() {
+        let result = json!([receipt(0x100, 1), receipt(0x100, 2)]);
+
+        let entries = HANDLER.derived_entries(&json!(["0x100"]), &result, &HeadTracker::default());
+        assert_eq!(
+            entries,
+            vec![
+                (
+                    "eth_getBlockReceipts",
+                    json!([format!("{:#066x}", 0x100)]),
+                    result.clone()
+                ),
+                (
+                    "eth_getTransactionReceipt",
+                    json!(["0x1"]),
+                    receipt(0x100, 1)
+                ),
+                (
+                    "eth_getTransactionReceipt",
+                    json!(["0x2"]),
+                    receipt(0x100, 2)
+                ),
+            ]
+        );
+
+        // by hash, the receipts are only the ones of their transactions in a canonical block
+        let params = json!([format!("{:#066x}", 0x100)]);
+        assert!(HANDLER
+            .derived_entries(&params, &result, &HeadTracker::default())
+            .is_empty());
+        let head = HeadTracker::at(0x100, None, None);
+        assert_eq!(HANDLER.derived_entries(&params, &result, &head).len(), 3);
+    }
+
+    #[test]
+    fn test_assemble_from_cache() {
+        let block = json!({"hash": format!("{:#066x}", 0x100), "transactions": ["0x1", "0x2"]});
+        let mut cached = HashMap::from([
+            (
+                ("eth_getBlockByNumber", json!(["0x100", false])),
+                block.clone(),
+            ),
+            (
+                ("eth_getTransactionReceipt", json!(["0x1"])),
+                receipt(0x100, 1),
+            ),
+        ]);
+        let assemble = |cached: &HashMap<(&str, Value), Value>| {
+            HANDLER.assemble_from_cache(&json!(["0x100"]), &mut |method, params| {
+                cached.get(&(method, params)).cloned()
+            })
+        };
+
+        // a receipt is missing
+        assert_eq!(assemble(&cached), None);
+
+        // a receipt of the transaction in another block
+        cached.insert(
+            ("eth_getTransactionReceipt", json!(["0x2"])),
+            receipt(0x101, 2),
+        );
+        assert_eq!(assemble(&cached), None);
+
+        cached.insert(
+            ("eth_getTransactionReceipt", json!(["0x2"])),
+            receipt(0x100, 2),
+        );
+        assert_eq!(
+            assemble(&cached),
+            Some(json!([receipt(0x100, 1), receipt(0x100, 2)]))
+        );
+    }
 }
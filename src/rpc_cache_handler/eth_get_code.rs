@@ -1,5 +1,6 @@
 use serde_json::Value;
 
+use crate::head_tracker::HeadTracker;
 use crate::rpc_cache_handler::RpcCacheHandler;
 
 #[derive(Default, Clone)]
@@ -15,4 +16,12 @@ impl RpcCacheHandler for Handler {
     fn extract_cache_key(&self, params: &Value) -> anyhow::Result<Option<String>> {
         self.inner.extract_cache_key(params)
     }
+
+    fn resolve_block_tags(&self, params: &mut Value, head: &HeadTracker) {
+        self.inner.resolve_block_tags(params, head)
+    }
+
+    fn extract_block_range(&self, params: &Value, result: &Value) -> Option<(u64, u64)> {
+        self.inner.extract_block_range(params, result)
+    }
 }
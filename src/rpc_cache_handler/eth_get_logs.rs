@@ -1,8 +1,9 @@
 use alloy_primitives::B256;
 use anyhow::{bail, Context};
-use serde_json::Value;
+use serde_json::{json, Value};
 use std::str::FromStr;
 
+use crate::head_tracker::HeadTracker;
 use crate::rpc_cache_handler::common::require_array_params;
 use crate::rpc_cache_handler::{common, RpcCacheHandler};
 
@@ -63,15 +64,88 @@ impl RpcCacheHandler for Handler {
 
         Ok(cache_key)
     }
+
+    fn resolve_block_tags(&self, params: &mut Value, head: &HeadTracker) {
+        let Some(filter) = params.get_mut(0).and_then(Value::as_object_mut) else {
+            return;
+        };
+        if filter.contains_key("blockHash") {
+            return;
+        }
+
+        for bound in ["fromBlock", "toBlock"] {
+            match filter.get_mut(bound) {
+                Some(value) if !value.is_null() => common::resolve_block_tag(Some(value), head),
+                // a missing bound defaults to the latest block
+                _ => {
+                    if let Some(latest) = head.resolve("latest") {
+                        filter.insert(bound.to_string(), format!("0x{latest:x}").into());
+                    }
+                }
+            }
+        }
+    }
+
+    fn extract_block_range(&self, params: &Value, result: &Value) -> Option<(u64, u64)> {
+        let filter = params.get(0)?;
+        if !filter["blockHash"].is_null() {
+            let number = common::parse_block_number(&result[0]["blockNumber"])?;
+            return Some((number, number));
+        }
+
+        let from_block = common::parse_block_number(&filter["fromBlock"])?;
+        let to_block = common::parse_block_number(&filter["toBlock"])?;
+        Some((from_block, to_block))
+    }
+
+    // no logs at a block hash don't tell the block, the head or the cached block does
+    fn locate_block_range(
+        &self,
+        params: &Value,
+        head: &HeadTracker,
+        lookup: &mut dyn FnMut(&str, Value) -> Option<Value>,
+    ) -> Option<(u64, u64)> {
+        let hash = common::extract_and_format_block_hash(&params.get(0)?["blockHash"]).ok()?;
+        let number = head.canonical_number(&hash).or_else(|| {
+            let block = lookup("eth_getBlockByHash", json!([hash, false]))?;
+            common::parse_block_number(&block["number"])
+        })?;
+        Some((number, number))
+    }
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
-    use serde_json::json;
 
     static HANDLER: Handler = Handler;
 
+    #[test]
+    fn test_resolve_block_tags() {
+        let head = HeadTracker::at(0x429d3c, Some(0x429d30), None);
+
+        let mut params = json!([{"fromBlock": "safe", "toBlock": "latest", "address": []}]);
+        HANDLER.resolve_block_tags(&mut params, &head);
+        assert_eq!(
+            params,
+            json!([{"fromBlock": "0x429d30", "toBlock": "0x429d3c", "address": []}])
+        );
+
+        let mut params = json!([{"address": []}]);
+        HANDLER.resolve_block_tags(&mut params, &head);
+        assert_eq!(
+            params,
+            json!([{"fromBlock": "0x429d3c", "toBlock": "0x429d3c", "address": []}])
+        );
+
+        let mut params = json!([{"fromBlock": "finalized", "blockHash": "0x12"}]);
+        HANDLER.resolve_block_tags(&mut params, &head);
+        assert_eq!(
+            params,
+            json!([{"fromBlock": "finalized", "blockHash": "0x12"}])
+        );
+    }
+
     #[test]
     fn test_block_range() {
         let params = json!([
@@ -123,6 +197,29 @@ mod test {
         );
     }
 
+    #[test]
+    fn test_block_hash_range() {
+        let hash = format!("{:#066x}", 0x1234);
+        let params = json!([{"blockHash": hash}]);
+        let logs = json!([{"blockNumber": "0x1234", "blockHash": hash}]);
+        assert_eq!(
+            HANDLER.extract_block_range(&params, &logs),
+            Some((0x1234, 0x1234))
+        );
+
+        // without logs, from the head or the cached block
+        assert_eq!(HANDLER.extract_block_range(&params, &json!([])), None);
+        let head = HeadTracker::at(0x1234, None, None);
+        let range = HANDLER.locate_block_range(&params, &head, &mut |_, _| None);
+        assert_eq!(range, Some((0x1234, 0x1234)));
+
+        let block = json!({"number": "0x1234", "hash": hash});
+        let range = HANDLER.locate_block_range(&params, &HeadTracker::default(), &mut |_, _| {
+            Some(block.clone())
+        });
+        assert_eq!(range, Some((0x1234, 0x1234)));
+    }
+
     #[test]
     fn test_invalid_block_number() {
         let params = json!([
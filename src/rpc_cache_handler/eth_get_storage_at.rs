@@ -2,6 +2,7 @@ use alloy_primitives::{Address, U256};
 use anyhow::{bail, Context};
 use serde_json::Value;
 
+use crate::head_tracker::HeadTracker;
 use crate::rpc_cache_handler::{common, RpcCacheHandler};
 
 #[derive(Default, Clone)]
@@ -44,6 +45,14 @@ impl RpcCacheHandler for Handler {
 
         Ok(Some(format!("{block_tag}-{lowercase_address}-{slot}")))
     }
+
+    fn resolve_block_tags(&self, params: &mut Value, head: &HeadTracker) {
+        common::resolve_block_tag(params.get_mut(2), head);
+    }
+
+    fn extract_block_range(&self, params: &Value, _result: &Value) -> Option<(u64, u64)> {
+        common::block_param_range(params.get(2))
+    }
 }
 
 #[cfg(test)]
@@ -36,6 +36,10 @@ impl RpcCacheHandler for Handler {
     ) -> anyhow::Result<(bool, CacheValue)> {
         common::extract_transaction_cache_value(result, reorg_ttl, self.get_ttl())
     }
+
+    fn extract_block_range(&self, params: &Value, _result: &Value) -> Option<(u64, u64)> {
+        common::block_param_range(params.get(0))
+    }
 }
 
 #[cfg(test)]
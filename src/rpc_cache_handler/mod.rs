@@ -1,8 +1,9 @@
 use crate::cache::CacheValue;
+use crate::head_tracker::HeadTracker;
 use anyhow::Result;
 use serde_json::Value;
 
-mod common;
+pub mod common;
 mod debug_trace_block_by_hash;
 mod debug_trace_block_by_number;
 mod debug_trace_call;
@@ -26,11 +27,23 @@ mod eth_get_transaction_count;
 mod eth_get_transaction_receipt;
 mod eth_max_priority_fee_per_gas;
 
+pub use eth_block_number::Handler as EthBlockNumber;
+pub use eth_get_block_by_number::Handler as EthGetBlockByNumber;
+
 pub trait RpcCacheHandler: Send + Sync {
     fn method_name(&self) -> &'static str;
 
     fn extract_cache_key(&self, params: &Value) -> Result<Option<String>>;
 
+    /// Rewrites the `latest`, `safe` and `finalized` block tags of the params to the block they
+    /// currently point at, so that the request becomes cacheable.
+    fn resolve_block_tags(&self, _params: &mut Value, _head: &HeadTracker) {}
+
+    /// Result known from the head of the chain alone, served without the cache or an upstream.
+    fn answer_from_head(&self, _params: &Value, _head: &HeadTracker) -> Option<Value> {
+        None
+    }
+
     fn extract_cache_value(&self, result: Value, reorg_ttl: u32) -> Result<(bool, CacheValue)> {
         // reorg_ttl is managed by cache backend
         Ok((
@@ -39,6 +52,48 @@ pub trait RpcCacheHandler: Send + Sync {
         ))
     }
 
+    /// Inclusive range of the blocks the result derives from, so that it can be dropped when one
+    /// of them is reorganized. By default the `blockNumber` of the result, e.g. of receipts and
+    /// transactions.
+    fn extract_block_range(&self, _params: &Value, result: &Value) -> Option<(u64, u64)> {
+        let number = common::parse_block_number(&result["blockNumber"])?;
+        Some((number, number))
+    }
+
+    /// Block range of a result which doesn't tell its block, e.g. a trace, from the head or the
+    /// cached results of other requests given by `lookup`. Tried when `extract_block_range`
+    /// finds none.
+    fn locate_block_range(
+        &self,
+        _params: &Value,
+        _head: &HeadTracker,
+        _lookup: &mut dyn FnMut(&str, Value) -> Option<Value>,
+    ) -> Option<(u64, u64)> {
+        None
+    }
+
+    /// Other requests answered by the result, as method, params and their own result, e.g. the
+    /// block of a number is also the block of its hash. Their entries are written along with the
+    /// entry of the request.
+    fn derived_entries(
+        &self,
+        _params: &Value,
+        _result: &Value,
+        _head: &HeadTracker,
+    ) -> Vec<(&'static str, Value, Value)> {
+        vec![]
+    }
+
+    /// Result put together from the cached results of other requests, given by `lookup` from
+    /// their method and params. Tried when the request itself isn't cached.
+    fn assemble_from_cache(
+        &self,
+        _params: &Value,
+        _lookup: &mut dyn FnMut(&str, Value) -> Option<Value>,
+    ) -> Option<Value> {
+        None
+    }
+
     // default ttl is 1 day
     fn get_ttl(&self) -> u32 {
         86400
@@ -0,0 +1,242 @@
+use std::collections::BTreeMap;
+use std::fmt::{Debug, Formatter};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::Context;
+use base64::engine::general_purpose::{STANDARD, URL_SAFE_NO_PAD};
+use base64::Engine;
+use hmac::{Hmac, Mac};
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue, AUTHORIZATION};
+use serde::Deserialize;
+use serde_json::json;
+use sha2::Sha256;
+
+/// A configuration value that must never end up in logs.
+#[derive(Deserialize, Clone, PartialEq, Eq)]
+#[serde(transparent)]
+pub struct Secret(String);
+
+impl Secret {
+    pub fn expose(&self) -> &str {
+        &self.0
+    }
+}
+
+impl Debug for Secret {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str("***")
+    }
+}
+
+/// Credentials sent with every request to an upstream.
+#[derive(Deserialize, Clone, Debug)]
+#[serde(tag = "type", rename_all = "kebab-case", deny_unknown_fields)]
+pub enum AuthConfig {
+    /// `Authorization: Bearer <token>`
+    Bearer { token: Secret },
+
+    /// API key in a custom header, `x-api-key` by default.
+    ApiKey {
+        #[serde(default = "default_api_key_header")]
+        header: String,
+        key: Secret,
+    },
+
+    /// HTTP basic authentication.
+    Basic {
+        username: String,
+        #[serde(default)]
+        password: Option<Secret>,
+    },
+
+    /// HS256 JWT signed with a hex encoded 32 bytes secret, as expected by the engine API of
+    /// execution clients. A fresh token is issued for every request.
+    Jwt {
+        #[serde(default)]
+        secret: Option<Secret>,
+        /// File holding the secret, e.g. the `jwt.hex` shared with the node.
+        #[serde(default)]
+        secret_file: Option<String>,
+    },
+}
+
+fn default_api_key_header() -> String {
+    "x-api-key".to_string()
+}
+
+/// Headers of an upstream, ready to be applied to each request.
+pub struct Auth {
+    headers: HeaderMap,
+    jwt_secret: Option<Vec<u8>>,
+}
+
+impl Auth {
+    pub fn new(
+        headers: &BTreeMap<String, Secret>,
+        config: Option<&AuthConfig>,
+    ) -> anyhow::Result<Self> {
+        let mut map = HeaderMap::new();
+        for (name, value) in headers {
+            map.insert(
+                HeaderName::try_from(name.as_str())
+                    .with_context(|| format!("invalid header name `{name}`"))?,
+                sensitive(value.expose())
+                    .with_context(|| format!("invalid value for header `{name}`"))?,
+            );
+        }
+
+        let mut jwt_secret = None;
+        match config {
+            Some(AuthConfig::Bearer { token }) => {
+                map.insert(
+                    AUTHORIZATION,
+                    sensitive(&format!("Bearer {}", token.expose()))
+                        .context("invalid bearer token")?,
+                );
+            }
+            Some(AuthConfig::ApiKey { header, key }) => {
+                map.insert(
+                    HeaderName::try_from(header.as_str())
+                        .with_context(|| format!("invalid header name `{header}`"))?,
+                    sensitive(key.expose()).context("invalid api key")?,
+                );
+            }
+            Some(AuthConfig::Basic { username, password }) => {
+                let credentials = format!(
+                    "{username}:{}",
+                    password.as_ref().map(Secret::expose).unwrap_or_default()
+                );
+                map.insert(
+                    AUTHORIZATION,
+                    sensitive(&format!("Basic {}", STANDARD.encode(credentials)))
+                        .context("invalid basic auth credentials")?,
+                );
+            }
+            Some(AuthConfig::Jwt {
+                secret,
+                secret_file,
+            }) => {
+                let secret = match (secret, secret_file) {
+                    (Some(secret), None) => secret.expose().to_string(),
+                    (None, Some(path)) => std::fs::read_to_string(path)
+                        .with_context(|| format!("fail to read jwt secret file {path}"))?,
+                    _ => anyhow::bail!("jwt auth needs exactly one of `secret` or `secret_file`"),
+                };
+
+                let secret = hex::decode(secret.trim().trim_start_matches("0x"))
+                    .ok()
+                    .filter(|secret| secret.len() == 32)
+                    .context("jwt secret must be 32 hex encoded bytes")?;
+                jwt_secret = Some(secret);
+            }
+            None => {}
+        }
+
+        Ok(Self {
+            headers: map,
+            jwt_secret,
+        })
+    }
+
+    pub fn headers(&self) -> HeaderMap {
+        let mut headers = self.headers.clone();
+
+        if let Some(secret) = &self.jwt_secret {
+            let iat = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs();
+            let token = jwt(secret, iat);
+            headers.insert(
+                AUTHORIZATION,
+                sensitive(&format!("Bearer {token}")).expect("jwt is a valid header value"),
+            );
+        }
+
+        headers
+    }
+}
+
+fn sensitive(value: &str) -> anyhow::Result<HeaderValue> {
+    let mut value = HeaderValue::from_str(value)?;
+    value.set_sensitive(true);
+    Ok(value)
+}
+
+fn jwt(secret: &[u8], iat: u64) -> String {
+    let header = URL_SAFE_NO_PAD.encode(json!({"alg": "HS256", "typ": "JWT"}).to_string());
+    let claims = URL_SAFE_NO_PAD.encode(json!({ "iat": iat }).to_string());
+    let message = format!("{header}.{claims}");
+
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret).expect("hmac accepts any key length");
+    mac.update(message.as_bytes());
+    let signature = URL_SAFE_NO_PAD.encode(mac.finalize().into_bytes());
+
+    format!("{message}.{signature}")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_headers() {
+        let headers = BTreeMap::from([("x-custom".to_string(), Secret("value".to_string()))]);
+        let auth = Auth::new(
+            &headers,
+            Some(&AuthConfig::Basic {
+                username: "user".to_string(),
+                password: Some(Secret("pass".to_string())),
+            }),
+        )
+        .unwrap();
+
+        let headers = auth.headers();
+        assert_eq!(headers["x-custom"], "value");
+        assert_eq!(headers[AUTHORIZATION], "Basic dXNlcjpwYXNz");
+        assert!(headers[AUTHORIZATION].is_sensitive());
+    }
+
+    #[test]
+    fn test_api_key() {
+        let auth = Auth::new(
+            &BTreeMap::new(),
+            Some(&AuthConfig::ApiKey {
+                header: default_api_key_header(),
+                key: Secret("key".to_string()),
+            }),
+        )
+        .unwrap();
+
+        assert_eq!(auth.headers()["x-api-key"], "key");
+    }
+
+    #[test]
+    fn test_jwt() {
+        let secret = [0u8; 32];
+        assert_eq!(
+            jwt(&secret, 1700000000),
+            "eyJhbGciOiJIUzI1NiIsInR5cCI6IkpXVCJ9.eyJpYXQiOjE3MDAwMDAwMDB9.\
+             AV0neG6Q-D2P_lDW7gragYW8jJ_BPpKgG5A6auv-Ef4"
+        );
+
+        let err = Auth::new(
+            &BTreeMap::new(),
+            Some(&AuthConfig::Jwt {
+                secret: Some(Secret("0x1234".to_string())),
+                secret_file: None,
+            }),
+        )
+        .err()
+        .unwrap();
+        assert!(!format!("{err:#}").contains("1234"));
+    }
+
+    #[test]
+    fn test_secret_debug() {
+        let config = AuthConfig::Bearer {
+            token: Secret("s3cr3t".to_string()),
+        };
+        assert!(!format!("{config:?}").contains("s3cr3t"));
+    }
+}
@@ -0,0 +1,180 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+
+use serde::Deserialize;
+
+use super::Upstream;
+
+#[derive(clap::ValueEnum, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum BalancePolicy {
+    /// Always prefer the first upstream, the others are only used for failover.
+    #[default]
+    Failover,
+    RoundRobin,
+    /// Smooth weighted round-robin using the `weight` of each upstream.
+    Weighted,
+    /// Prefer the upstream with the lowest latency (EWMA).
+    Latency,
+}
+
+/// Decides in which order the upstreams of a pool are tried for each outgoing batch. The first
+/// upstream is the selected one, the remaining ones are the failover order.
+pub struct Balancer {
+    policy: BalancePolicy,
+    next: AtomicUsize,
+    current_weights: Mutex<Vec<i64>>,
+}
+
+impl Balancer {
+    pub fn new(policy: BalancePolicy, upstreams: usize) -> Self {
+        Self {
+            policy,
+            next: AtomicUsize::new(0),
+            current_weights: Mutex::new(vec![0; upstreams]),
+        }
+    }
+
+    /// Order of the `members` of a group. Unhealthy upstreams and upstreams with an open circuit
+    /// breaker are left out of the rotation, unless none of them is available.
+    pub fn order(&self, upstreams: &[Upstream], members: &[usize]) -> Vec<usize> {
+        let mut order: Vec<usize> = members
+            .iter()
+            .copied()
+            .filter(|index| upstreams[*index].is_available())
+            .collect();
+
+        if order.is_empty() {
+            order = members.to_vec();
+        }
+
+        match self.policy {
+            BalancePolicy::Failover => {}
+            BalancePolicy::RoundRobin => {
+                let first = self.next.fetch_add(1, Ordering::Relaxed) % order.len();
+                order.rotate_left(first);
+            }
+            BalancePolicy::Weighted => {
+                let first = self.next_weighted(upstreams, &order);
+                order.retain(|index| *index != first);
+                order.insert(0, first);
+            }
+            BalancePolicy::Latency => {
+                // upstreams without samples yet sort first so that they get measured
+                order.sort_by(|a, b| {
+                    let a = upstreams[*a].latency().unwrap_or(0.0);
+                    let b = upstreams[*b].latency().unwrap_or(0.0);
+                    a.total_cmp(&b)
+                });
+            }
+        }
+
+        order
+    }
+
+    fn next_weighted(&self, upstreams: &[Upstream], candidates: &[usize]) -> usize {
+        let mut current_weights = self.current_weights.lock().unwrap();
+        let total: i64 = candidates
+            .iter()
+            .map(|index| upstreams[*index].weight as i64)
+            .sum();
+
+        let mut selected = candidates[0];
+        for index in candidates.iter().copied() {
+            current_weights[index] += upstreams[index].weight as i64;
+            if current_weights[index] > current_weights[selected] {
+                selected = index;
+            }
+        }
+
+        current_weights[selected] -= total;
+        selected
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::config::UpstreamConfig;
+    use reqwest::Url;
+
+    fn upstreams(weights: &[u32]) -> Vec<Upstream> {
+        weights
+            .iter()
+            .enumerate()
+            .map(|(index, weight)| {
+                let url = Url::parse(&format!("http://node{index}")).unwrap();
+                let mut config = UpstreamConfig::new(url);
+                config.weight = *weight;
+                Upstream::new(config).unwrap()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_failover() {
+        let upstreams = upstreams(&[1, 1, 1]);
+        let balancer = Balancer::new(BalancePolicy::Failover, upstreams.len());
+
+        assert_eq!(balancer.order(&upstreams, &[0, 1, 2]), vec![0, 1, 2]);
+        assert_eq!(balancer.order(&upstreams, &[0, 1, 2]), vec![0, 1, 2]);
+    }
+
+    #[test]
+    fn test_round_robin() {
+        let upstreams = upstreams(&[1, 1, 1]);
+        let balancer = Balancer::new(BalancePolicy::RoundRobin, upstreams.len());
+
+        assert_eq!(balancer.order(&upstreams, &[0, 1, 2]), vec![0, 1, 2]);
+        assert_eq!(balancer.order(&upstreams, &[0, 1, 2]), vec![1, 2, 0]);
+        assert_eq!(balancer.order(&upstreams, &[0, 1, 2]), vec![2, 0, 1]);
+        assert_eq!(balancer.order(&upstreams, &[0, 1, 2]), vec![0, 1, 2]);
+    }
+
+    #[test]
+    fn test_weighted() {
+        let upstreams = upstreams(&[5, 1, 1]);
+        let balancer = Balancer::new(BalancePolicy::Weighted, upstreams.len());
+
+        let selected: Vec<usize> = (0..7)
+            .map(|_| balancer.order(&upstreams, &[0, 1, 2])[0])
+            .collect();
+        assert_eq!(selected, vec![0, 0, 1, 0, 2, 0, 0]);
+    }
+
+    #[test]
+    fn test_skip_unhealthy() {
+        let upstreams = upstreams(&[1, 1, 1]);
+        let balancer = Balancer::new(BalancePolicy::RoundRobin, upstreams.len());
+
+        upstreams[1].set_healthy(false);
+        assert_eq!(balancer.order(&upstreams, &[0, 1, 2]), vec![0, 2]);
+        assert_eq!(balancer.order(&upstreams, &[0, 1, 2]), vec![2, 0]);
+
+        upstreams[0].set_healthy(false);
+        upstreams[2].set_healthy(false);
+        assert_eq!(balancer.order(&upstreams, &[0, 1, 2]).len(), 3);
+    }
+
+    #[test]
+    fn test_group_members() {
+        let upstreams = upstreams(&[1, 1, 1]);
+        let balancer = Balancer::new(BalancePolicy::RoundRobin, upstreams.len());
+
+        assert_eq!(balancer.order(&upstreams, &[0, 2]), vec![0, 2]);
+        assert_eq!(balancer.order(&upstreams, &[0, 2]), vec![2, 0]);
+    }
+
+    #[test]
+    fn test_latency() {
+        let upstreams = upstreams(&[1, 1, 1]);
+        let balancer = Balancer::new(BalancePolicy::Latency, upstreams.len());
+
+        upstreams[0].record_latency(0.3);
+        upstreams[1].record_latency(0.1);
+        assert_eq!(balancer.order(&upstreams, &[0, 1, 2]), vec![2, 1, 0]);
+
+        upstreams[2].record_latency(0.2);
+        assert_eq!(balancer.order(&upstreams, &[0, 1, 2]), vec![1, 2, 0]);
+    }
+}
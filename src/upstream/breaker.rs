@@ -0,0 +1,181 @@
+use std::collections::VecDeque;
+use std::fmt::{Display, Formatter};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+#[derive(Clone, Debug)]
+pub struct BreakerPolicy {
+    /// Number of failures within `window` opening the circuit.
+    pub threshold: usize,
+    pub window: Duration,
+    /// How long the circuit stays open before letting a probe request through.
+    pub open_duration: Duration,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum State {
+    Closed,
+    Open,
+    HalfOpen,
+}
+
+impl State {
+    /// Value of the state gauge.
+    pub fn as_i64(self) -> i64 {
+        match self {
+            State::Closed => 0,
+            State::HalfOpen => 1,
+            State::Open => 2,
+        }
+    }
+}
+
+impl Display for State {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            State::Closed => write!(f, "closed"),
+            State::Open => write!(f, "open"),
+            State::HalfOpen => write!(f, "half-open"),
+        }
+    }
+}
+
+/// Stops sending requests to an upstream which keeps failing. Once open, the circuit lets a
+/// single probe request through after `open_duration`, and closes again when it succeeds.
+pub struct CircuitBreaker {
+    policy: BreakerPolicy,
+    inner: Mutex<Inner>,
+}
+
+struct Inner {
+    state: State,
+    failures: VecDeque<Instant>,
+    /// When the circuit opened, or when the probe was let through in the half-open state.
+    since: Instant,
+}
+
+impl CircuitBreaker {
+    pub fn new(policy: BreakerPolicy) -> Self {
+        Self {
+            policy,
+            inner: Mutex::new(Inner {
+                state: State::Closed,
+                failures: VecDeque::new(),
+                since: Instant::now(),
+            }),
+        }
+    }
+
+    /// Whether requests are currently refused.
+    pub fn is_open(&self) -> bool {
+        let inner = self.inner.lock().unwrap();
+        match inner.state {
+            State::Closed => false,
+            State::Open | State::HalfOpen => inner.since.elapsed() < self.policy.open_duration,
+        }
+    }
+
+    /// Whether a request may be sent, along with the new state when it changed.
+    pub fn allow(&self) -> (bool, Option<State>) {
+        let mut inner = self.inner.lock().unwrap();
+
+        match inner.state {
+            State::Closed => (true, None),
+            // a probe is in flight, a probe that never came back is replaced after a while
+            State::Open | State::HalfOpen if inner.since.elapsed() < self.policy.open_duration => {
+                (false, None)
+            }
+            State::Open => {
+                inner.state = State::HalfOpen;
+                inner.since = Instant::now();
+                (true, Some(State::HalfOpen))
+            }
+            State::HalfOpen => {
+                inner.since = Instant::now();
+                (true, None)
+            }
+        }
+    }
+
+    /// Record the outcome of a request and return the new state when it changed.
+    pub fn record(&self, success: bool) -> Option<State> {
+        let mut inner = self.inner.lock().unwrap();
+        let now = Instant::now();
+
+        match (inner.state, success) {
+            (State::Closed, true) => None,
+            (State::Closed, false) => {
+                inner.failures.push_back(now);
+                while inner
+                    .failures
+                    .front()
+                    .is_some_and(|at| now.duration_since(*at) > self.policy.window)
+                {
+                    inner.failures.pop_front();
+                }
+
+                if inner.failures.len() < self.policy.threshold {
+                    return None;
+                }
+
+                inner.state = State::Open;
+                inner.since = now;
+                inner.failures.clear();
+                Some(State::Open)
+            }
+            (State::HalfOpen, true) => {
+                inner.state = State::Closed;
+                Some(State::Closed)
+            }
+            (State::HalfOpen, false) => {
+                inner.state = State::Open;
+                inner.since = now;
+                Some(State::Open)
+            }
+            // outcome of a request sent before the circuit opened
+            (State::Open, _) => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn breaker(open_duration: Duration) -> CircuitBreaker {
+        CircuitBreaker::new(BreakerPolicy {
+            threshold: 3,
+            window: Duration::from_secs(10),
+            open_duration,
+        })
+    }
+
+    #[test]
+    fn test_open() {
+        let breaker = breaker(Duration::from_secs(30));
+
+        assert_eq!(breaker.record(false), None);
+        assert_eq!(breaker.record(true), None);
+        assert_eq!(breaker.record(false), None);
+        assert_eq!(breaker.allow(), (true, None));
+        assert_eq!(breaker.record(false), Some(State::Open));
+
+        assert!(breaker.is_open());
+        assert_eq!(breaker.allow(), (false, None));
+    }
+
+    #[test]
+    fn test_half_open() {
+        let breaker = breaker(Duration::ZERO);
+        for _ in 0..3 {
+            breaker.record(false);
+        }
+
+        assert_eq!(breaker.allow(), (true, Some(State::HalfOpen)));
+        assert_eq!(breaker.record(false), Some(State::Open));
+
+        assert_eq!(breaker.allow(), (true, Some(State::HalfOpen)));
+        assert_eq!(breaker.record(true), Some(State::Closed));
+        assert!(!breaker.is_open());
+    }
+}
@@ -0,0 +1,135 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::Context;
+use serde_json::json;
+
+use super::{Upstream, UpstreamPool};
+use crate::metrics::Metrics;
+
+/// Periodically ask every upstream of the pool for its head and take the ones that error or
+/// lag more than `max_head_lag` blocks behind the best head out of the rotation.
+pub fn spawn(pool: Arc<UpstreamPool>, metrics: Metrics, interval: Duration, max_head_lag: u64) {
+    let client = reqwest::Client::builder()
+        .timeout(interval)
+        .build()
+        .expect("fail to build health check http client");
+
+    actix_web::rt::spawn(async move {
+        let mut ticker = actix_web::rt::time::interval(interval);
+
+        loop {
+            ticker.tick().await;
+
+            let heads = futures::future::join_all(
+                pool.upstreams
+                    .iter()
+                    .map(|upstream| probe(&client, upstream, interval)),
+            )
+            .await;
+
+            let heads: Vec<Option<u64>> = heads
+                .into_iter()
+                .zip(pool.upstreams.iter())
+                .map(|(head, upstream)| match head {
+                    Ok(head) => Some(head),
+                    Err(err) => {
+                        tracing::warn!(
+                            chain = pool.chain,
+                            upstream = upstream.name,
+                            "health check failed: {err:#}"
+                        );
+                        None
+                    }
+                })
+                .collect();
+
+            let best_head = heads.iter().flatten().max().copied();
+            let health = evaluate(&heads, max_head_lag);
+
+            for ((upstream, head), healthy) in pool.upstreams.iter().zip(heads).zip(health) {
+                let labels = [pool.chain.as_str(), upstream.name.as_str()];
+
+                if let Some(head) = head {
+                    upstream.set_head(head);
+                    metrics
+                        .upstream_head_block_gauge
+                        .with_label_values(&labels)
+                        .set(head as i64);
+                }
+
+                metrics
+                    .upstream_healthy_gauge
+                    .with_label_values(&labels)
+                    .set(healthy as i64);
+
+                if upstream.set_healthy(healthy) != healthy {
+                    if healthy {
+                        tracing::info!(
+                            chain = pool.chain,
+                            upstream = upstream.name,
+                            "upstream recovered, back in rotation"
+                        );
+                    } else {
+                        tracing::warn!(
+                            chain = pool.chain,
+                            upstream = upstream.name,
+                            head,
+                            best_head,
+                            "upstream unhealthy, removed from rotation"
+                        );
+                    }
+                }
+            }
+        }
+    });
+}
+
+async fn probe(
+    client: &reqwest::Client,
+    upstream: &Upstream,
+    timeout: Duration,
+) -> anyhow::Result<u64> {
+    let request = json!({
+        "jsonrpc": "2.0",
+        "method": "eth_blockNumber",
+        "params": [],
+        "id": 1
+    });
+
+    let response = upstream.request(client, &request, Some(timeout)).await?;
+    let block_number = response["result"]
+        .as_str()
+        .with_context(|| format!("invalid eth_blockNumber response: {response}"))?;
+
+    u64::from_str_radix(block_number.trim_start_matches("0x"), 16)
+        .context("invalid eth_blockNumber result")
+}
+
+/// An upstream is healthy when it answered and is at most `max_head_lag` blocks behind the
+/// best head seen across the pool.
+fn evaluate(heads: &[Option<u64>], max_head_lag: u64) -> Vec<bool> {
+    let best_head = heads.iter().flatten().max().copied().unwrap_or_default();
+
+    heads
+        .iter()
+        .map(|head| matches!(head, Some(head) if head + max_head_lag >= best_head))
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_evaluate() {
+        let health = evaluate(&[Some(100), Some(97), Some(94), None], 5);
+        assert_eq!(health, vec![true, true, false, false]);
+    }
+
+    #[test]
+    fn test_evaluate_all_failed() {
+        let health = evaluate(&[None, None], 5);
+        assert_eq!(health, vec![false, false]);
+    }
+}
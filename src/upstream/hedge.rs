@@ -0,0 +1,112 @@
+use std::time::Duration;
+
+use serde::Deserialize;
+
+/// Send read-only requests to a second upstream when the first one is slower than usual.
+#[derive(Deserialize, Clone, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct HedgeConfig {
+    /// Latency quantile of the primary upstream after which the hedge is sent.
+    #[serde(default = "default_quantile")]
+    pub quantile: f64,
+
+    #[serde(default = "default_min_delay_ms")]
+    pub min_delay_ms: u64,
+
+    /// Also used while the latency of the primary upstream is unknown.
+    #[serde(default = "default_max_delay_ms")]
+    pub max_delay_ms: u64,
+}
+
+impl Default for HedgeConfig {
+    fn default() -> Self {
+        Self {
+            quantile: default_quantile(),
+            min_delay_ms: default_min_delay_ms(),
+            max_delay_ms: default_max_delay_ms(),
+        }
+    }
+}
+
+fn default_quantile() -> f64 {
+    0.95
+}
+
+fn default_min_delay_ms() -> u64 {
+    20
+}
+
+fn default_max_delay_ms() -> u64 {
+    1000
+}
+
+impl HedgeConfig {
+    /// Delay before hedging, given the recent latencies of the primary upstream in seconds.
+    pub fn delay(&self, samples: &[f64]) -> Duration {
+        let min = Duration::from_millis(self.min_delay_ms);
+        let max = Duration::from_millis(self.max_delay_ms).max(min);
+
+        match quantile(samples, self.quantile) {
+            Some(seconds) => Duration::from_secs_f64(seconds).clamp(min, max),
+            None => max,
+        }
+    }
+}
+
+fn quantile(samples: &[f64], quantile: f64) -> Option<f64> {
+    if samples.is_empty() {
+        return None;
+    }
+
+    let mut sorted = samples.to_vec();
+    sorted.sort_by(f64::total_cmp);
+
+    let rank = (quantile.clamp(0.0, 1.0) * (sorted.len() - 1) as f64).round() as usize;
+    Some(sorted[rank])
+}
+
+/// Methods which only read chain data and can safely be sent twice. Filters are excluded since
+/// they live on a single node.
+pub fn is_read_only(method: &str) -> bool {
+    match method {
+        "eth_getFilterChanges" | "eth_getFilterLogs" | "eth_getWork" => false,
+        "eth_blockNumber"
+        | "eth_call"
+        | "eth_chainId"
+        | "eth_estimateGas"
+        | "eth_feeHistory"
+        | "eth_gasPrice"
+        | "eth_maxPriorityFeePerGas"
+        | "eth_blobBaseFee"
+        | "eth_syncing"
+        | "net_version" => true,
+        method => method.starts_with("eth_get"),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_delay() {
+        let config = HedgeConfig::default();
+        assert_eq!(config.delay(&[]), Duration::from_secs(1));
+
+        let samples: Vec<f64> = (1..=100).map(|ms| ms as f64 / 1000.0).collect();
+        let delay = config.delay(&samples);
+        assert!(delay.abs_diff(Duration::from_millis(95)) < Duration::from_millis(1));
+
+        assert_eq!(config.delay(&[0.001]), Duration::from_millis(20));
+        assert_eq!(config.delay(&[30.0]), Duration::from_secs(1));
+    }
+
+    #[test]
+    fn test_read_only() {
+        assert!(is_read_only("eth_call"));
+        assert!(is_read_only("eth_getBlockByNumber"));
+        assert!(!is_read_only("eth_getFilterChanges"));
+        assert!(!is_read_only("eth_sendRawTransaction"));
+        assert!(!is_read_only("debug_traceTransaction"));
+    }
+}
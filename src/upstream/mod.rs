@@ -0,0 +1,1442 @@
+mod auth;
+mod balancer;
+mod breaker;
+pub mod health;
+mod hedge;
+mod quorum;
+mod rate_limit;
+mod retry;
+mod routing;
+mod socket;
+#[cfg(test)]
+pub mod testing;
+mod timeout;
+
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use std::fmt::{Display, Formatter};
+use std::pin::pin;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use anyhow::Context;
+use futures::future::{self, Either};
+use reqwest::header::HeaderMap;
+use reqwest::{StatusCode, Url};
+use serde::Serialize;
+use serde_json::{json, Value};
+
+use crate::config::UpstreamConfig;
+use crate::json_rpc::{DefinedError, JsonRpcResponse, RequestId};
+use crate::metrics::Metrics;
+use crate::{utils, RpcRequest};
+
+use auth::Auth;
+pub use auth::{AuthConfig, Secret};
+pub use balancer::BalancePolicy;
+use balancer::Balancer;
+pub use breaker::BreakerPolicy;
+use breaker::{CircuitBreaker, State as BreakerState};
+pub use hedge::HedgeConfig;
+pub use quorum::QuorumConfig;
+use quorum::Verdict;
+pub use rate_limit::RateLimitConfig;
+use rate_limit::RateLimiter;
+pub use retry::RetryPolicy;
+use routing::Router;
+pub use routing::{RouteConfig, DEFAULT_GROUP};
+use socket::{Endpoint, SocketClient};
+pub use timeout::Timeouts;
+
+/// Smoothing factor of the latency moving average, higher values react faster.
+const LATENCY_EWMA_ALPHA: f64 = 0.2;
+
+/// Number of recent latencies kept to derive the hedging delay.
+const LATENCY_SAMPLES: usize = 256;
+
+enum Transport {
+    /// A new HTTP request for each batch.
+    Http,
+    /// Persistent connection, for `ws://`, `wss://` and `ipc://` urls.
+    Socket(SocketClient),
+}
+
+pub struct Upstream {
+    pub name: String,
+    pub url: Url,
+    transport: Transport,
+    auth: Auth,
+    group: String,
+    weight: u32,
+    batch: bool,
+    max_batch_size: Option<usize>,
+    rate_limiter: Option<RateLimiter>,
+    breaker: Option<CircuitBreaker>,
+    latency: Mutex<Option<f64>>,
+    samples: Mutex<VecDeque<f64>>,
+    healthy: AtomicBool,
+    head: AtomicU64,
+}
+
+impl Upstream {
+    pub fn new(config: UpstreamConfig) -> anyhow::Result<Self> {
+        let name = config
+            .name
+            .or_else(|| {
+                let host = config.url.host_str()?;
+                Some(match config.url.port() {
+                    Some(port) => format!("{host}:{port}"),
+                    None => host.to_string(),
+                })
+            })
+            .unwrap_or_else(|| utils::redact_url(&config.url));
+
+        let auth = Auth::new(&config.headers, config.auth.as_ref())
+            .with_context(|| format!("invalid credentials for upstream `{name}`"))?;
+        let rate_limiter = config
+            .rate_limit
+            .map(RateLimiter::new)
+            .transpose()
+            .with_context(|| format!("invalid rate limit for upstream `{name}`"))?;
+
+        let transport = match config.url.scheme() {
+            "http" | "https" => Transport::Http,
+            "ws" | "wss" => Transport::Socket(SocketClient::new(Endpoint::Ws(config.url.clone()))),
+            "ipc" => Transport::Socket(SocketClient::new(Endpoint::Ipc(config.url.path().into()))),
+            scheme => anyhow::bail!("unsupported scheme `{scheme}` for upstream `{name}`"),
+        };
+
+        Ok(Self {
+            name,
+            url: config.url,
+            transport,
+            auth,
+            group: config.group,
+            weight: config.weight,
+            batch: config.batch,
+            max_batch_size: config.max_batch_size,
+            rate_limiter,
+            breaker: None,
+            latency: Mutex::new(None),
+            samples: Mutex::new(VecDeque::with_capacity(LATENCY_SAMPLES)),
+            healthy: AtomicBool::new(true),
+            head: AtomicU64::new(0),
+        })
+    }
+
+    /// Send a raw JSON-RPC request or batch over the transport of the upstream.
+    pub async fn request<T: Serialize + ?Sized>(
+        &self,
+        client: &reqwest::Client,
+        body: &T,
+        timeout: Option<Duration>,
+    ) -> Result<Value, UpstreamError> {
+        match &self.transport {
+            Transport::Http => {
+                utils::do_rpc_request(client, self.url.clone(), self.headers(), body, timeout).await
+            }
+            Transport::Socket(socket) => {
+                let body = serde_json::to_value(body).expect("json-rpc request is serializable");
+                socket.request(self.headers(), body, timeout).await
+            }
+        }
+    }
+
+    pub async fn chain_id(&self, client: &reqwest::Client) -> anyhow::Result<u64> {
+        let request = json!({
+            "jsonrpc": "2.0",
+            "method": "eth_chainId",
+            "params": [],
+            "id": 1
+        });
+
+        let response = self.request(client, &request, None).await?;
+        match response["result"].as_str() {
+            Some(chain_id) => Ok(u64::from_str_radix(chain_id.trim_start_matches("0x"), 16)?),
+            None => Err(anyhow::anyhow!("fail to get chain id: {response}")),
+        }
+    }
+
+    /// Headers to send with every request, including credentials.
+    pub fn headers(&self) -> HeaderMap {
+        self.auth.headers()
+    }
+
+    pub fn is_healthy(&self) -> bool {
+        self.healthy.load(Ordering::Relaxed)
+    }
+
+    /// Healthy, and not refused by its circuit breaker.
+    pub fn is_available(&self) -> bool {
+        self.is_healthy() && !self.breaker.as_ref().is_some_and(|b| b.is_open())
+    }
+
+    /// Returns the previous health status.
+    fn set_healthy(&self, healthy: bool) -> bool {
+        self.healthy.swap(healthy, Ordering::Relaxed)
+    }
+
+    /// Latest block number reported by the health check, zero if unknown.
+    pub fn head(&self) -> u64 {
+        self.head.load(Ordering::Relaxed)
+    }
+
+    fn set_head(&self, head: u64) {
+        self.head.store(head, Ordering::Relaxed);
+    }
+
+    /// Send the requests, split in concurrent chunks according to the batching settings of the
+    /// upstream. Requests with different timeouts never share a chunk, so that a slow method
+    /// timing out does not take the rest of the batch down with it. Chunks over the rate limit
+    /// wait for capacity when `wait` is set, otherwise they fail with
+    /// [`UpstreamError::Throttled`]. Returns the outcome of each chunk.
+    async fn send<'a>(
+        &self,
+        client: &reqwest::Client,
+        metrics: &Metrics,
+        chain: &str,
+        timeouts: &Timeouts,
+        requests: &[&'a RpcRequest],
+        wait: bool,
+    ) -> ChunkResults<'a> {
+        let mut by_timeout: BTreeMap<Duration, Vec<&'a RpcRequest>> = BTreeMap::new();
+        for request in requests {
+            by_timeout
+                .entry(timeouts.get(&request.method))
+                .or_default()
+                .push(request);
+        }
+
+        let mut chunks: Vec<(Duration, Vec<&'a RpcRequest>)> = vec![];
+        for (timeout, requests) in by_timeout {
+            chunks.extend(self.chunks(requests).map(|chunk| (timeout, chunk)));
+        }
+
+        // the breaker counts the call once, however many chunks it is split in
+        if let Some(breaker) = &self.breaker {
+            let (allowed, transition) = breaker.allow();
+            self.report_transition(metrics, chain, transition);
+            if !allowed {
+                return chunks
+                    .into_iter()
+                    .map(|(_, chunk)| (chunk, Err(UpstreamError::CircuitOpen)))
+                    .collect();
+            }
+        }
+
+        let results =
+            futures::future::join_all(chunks.into_iter().map(|(timeout, chunk)| async move {
+                let result = self
+                    .call(client, metrics, chain, timeout, &chunk, wait)
+                    .await;
+                (chunk, result)
+            }))
+            .await;
+
+        if let Some(breaker) = &self.breaker {
+            // chunks over the rate limit never reached the upstream
+            let sent = results
+                .iter()
+                .filter(|(_, result)| !matches!(result, Err(UpstreamError::Throttled)))
+                .collect::<Vec<_>>();
+            if !sent.is_empty() {
+                let failed = sent
+                    .iter()
+                    .any(|(_, result)| matches!(result, Err(err) if err.should_failover()));
+                self.report_transition(metrics, chain, breaker.record(!failed));
+            }
+        }
+
+        results
+    }
+
+    /// Send the requests straight over the transport, chunked like [`Upstream::send`] but
+    /// bypassing the rate limiter and the circuit breaker, for the background polls of the
+    /// proxy itself. Fails when any chunk fails.
+    pub async fn send_direct(
+        &self,
+        client: &reqwest::Client,
+        requests: &[RpcRequest],
+        timeout: Duration,
+    ) -> Result<Vec<Value>, UpstreamError> {
+        let chunks = self.chunks(requests.iter().collect());
+        let results = futures::future::join_all(
+            chunks.map(|chunk| async move { self.exchange(client, &chunk, timeout).await }),
+        )
+        .await;
+
+        let mut values = Vec::with_capacity(requests.len());
+        for result in results {
+            values.extend(result?);
+        }
+        Ok(values)
+    }
+
+    /// Split the requests in the chunks sent at once, according to the batching settings.
+    fn chunks<'a>(
+        &self,
+        requests: Vec<&'a RpcRequest>,
+    ) -> impl Iterator<Item = Vec<&'a RpcRequest>> {
+        let size = match (self.batch, self.max_batch_size) {
+            (false, _) => 1,
+            (true, Some(max_batch_size)) => max_batch_size.max(1),
+            (true, None) => requests.len().max(1),
+        };
+
+        let mut requests = requests.into_iter().peekable();
+        std::iter::from_fn(move || {
+            requests.peek()?;
+            Some(requests.by_ref().take(size).collect())
+        })
+    }
+
+    /// Send a chunk of requests, as a batch or as a single request depending on the upstream,
+    /// and return the responses.
+    async fn exchange(
+        &self,
+        client: &reqwest::Client,
+        requests: &[&RpcRequest],
+        timeout: Duration,
+    ) -> Result<Vec<Value>, UpstreamError> {
+        let response = match self.batch {
+            true => self.request(client, requests, Some(timeout)).await?,
+            false => self.request(client, requests[0], Some(timeout)).await?,
+        };
+
+        match (self.batch, response) {
+            (true, Value::Array(values)) => Ok(values),
+            (false, value @ Value::Object(_)) => Ok(vec![value]),
+            (_, response) => Err(UpstreamError::InvalidResponse(response)),
+        }
+    }
+
+    async fn call(
+        &self,
+        client: &reqwest::Client,
+        metrics: &Metrics,
+        chain: &str,
+        timeout: Duration,
+        requests: &[&RpcRequest],
+        wait: bool,
+    ) -> Result<Vec<Value>, UpstreamError> {
+        let labels = [chain, self.name.as_str()];
+
+        if let Some(limiter) = &self.rate_limiter {
+            self.throttle(limiter, metrics, chain, requests, wait)
+                .await?;
+        }
+
+        metrics
+            .upstream_selected_counter
+            .with_label_values(&labels)
+            .inc();
+
+        let start = Instant::now();
+        let values = self.exchange(client, requests, timeout).await?;
+
+        let elapsed = start.elapsed().as_secs_f64();
+        let ewma = self.record_latency(elapsed);
+        metrics
+            .upstream_latency_histogram
+            .with_label_values(&labels)
+            .observe(elapsed);
+        metrics
+            .upstream_latency_ewma_gauge
+            .with_label_values(&labels)
+            .set(ewma);
+
+        Ok(values)
+    }
+
+    fn report_transition(&self, metrics: &Metrics, chain: &str, transition: Option<BreakerState>) {
+        let Some(state) = transition else {
+            return;
+        };
+
+        let labels = [chain, self.name.as_str()];
+        metrics
+            .upstream_circuit_state_gauge
+            .with_label_values(&labels)
+            .set(state.as_i64());
+        metrics
+            .upstream_circuit_transition_counter
+            .with_label_values(&[chain, self.name.as_str(), &state.to_string()])
+            .inc();
+
+        match state {
+            BreakerState::Open => tracing::warn!(
+                chain,
+                upstream = self.name,
+                "circuit breaker open, failing fast"
+            ),
+            state => tracing::info!(chain, upstream = self.name, "circuit breaker {state}"),
+        }
+    }
+
+    async fn throttle(
+        &self,
+        limiter: &RateLimiter,
+        metrics: &Metrics,
+        chain: &str,
+        requests: &[&RpcRequest],
+        wait: bool,
+    ) -> Result<(), UpstreamError> {
+        let cost = limiter.cost(requests.iter().map(|request| request.method.as_str()));
+
+        let delay = match wait {
+            true => limiter.acquire(cost),
+            false => limiter.try_acquire(cost).then_some(Duration::ZERO),
+        };
+
+        let outcome = match delay {
+            Some(delay) if delay.is_zero() => return Ok(()),
+            Some(_) => "queued",
+            None if wait => "rejected",
+            None => "failover",
+        };
+
+        metrics
+            .upstream_throttled_counter
+            .with_label_values(&[chain, self.name.as_str(), outcome])
+            .inc();
+
+        let delay = delay.ok_or(UpstreamError::Throttled)?;
+
+        let queue = metrics
+            .upstream_rate_limit_queue_gauge
+            .with_label_values(&[chain, self.name.as_str()]);
+        queue.inc();
+        actix_web::rt::time::sleep(delay).await;
+        queue.dec();
+
+        Ok(())
+    }
+
+    /// Moving average of the latency in seconds, `None` until the first successful request.
+    pub fn latency(&self) -> Option<f64> {
+        *self.latency.lock().unwrap()
+    }
+
+    /// Latencies of the most recent successful requests, in seconds.
+    fn latency_samples(&self) -> Vec<f64> {
+        self.samples.lock().unwrap().iter().copied().collect()
+    }
+
+    fn record_latency(&self, seconds: f64) -> f64 {
+        {
+            let mut samples = self.samples.lock().unwrap();
+            if samples.len() == LATENCY_SAMPLES {
+                samples.pop_front();
+            }
+            samples.push_back(seconds);
+        }
+
+        let mut latency = self.latency.lock().unwrap();
+        let ewma = match *latency {
+            Some(ewma) => ewma + LATENCY_EWMA_ALPHA * (seconds - ewma),
+            None => seconds,
+        };
+        *latency = Some(ewma);
+        ewma
+    }
+}
+
+/// Upstreams serving the same chain, split in groups that requests are routed to by method.
+pub struct UpstreamPool {
+    chain: String,
+    upstreams: Vec<Upstream>,
+    groups: HashMap<String, UpstreamGroup>,
+    router: Router,
+    retry: RetryPolicy,
+    timeouts: Timeouts,
+    hedge: Option<HedgeConfig>,
+    quorum: Option<QuorumConfig>,
+}
+
+/// Settings of an [`UpstreamPool`] besides its upstreams and routes.
+pub struct PoolOptions {
+    pub policy: BalancePolicy,
+    pub retry: RetryPolicy,
+    pub timeouts: Timeouts,
+    pub hedge: Option<HedgeConfig>,
+    pub quorum: Option<QuorumConfig>,
+    pub breaker: Option<BreakerPolicy>,
+}
+
+/// Responses of [`UpstreamPool::send`].
+#[derive(Default)]
+pub struct Responses {
+    /// One JSON-RPC response per request, in no particular order.
+    pub values: Vec<Value>,
+    /// Requests answered without the agreement of the quorum, they must not be cached.
+    pub unconfirmed: HashSet<RequestId>,
+}
+
+impl Responses {
+    /// Whether the response to the request may be cached: not confirmed by the quorum, it may
+    /// come from a single faulty upstream which must not poison the cache.
+    pub fn is_cacheable(&self, id: &RequestId) -> bool {
+        !self.unconfirmed.contains(id)
+    }
+}
+
+struct UpstreamGroup {
+    members: Vec<usize>,
+    balancer: Balancer,
+}
+
+impl UpstreamPool {
+    pub fn new(
+        chain: String,
+        configs: Vec<UpstreamConfig>,
+        routes: Vec<RouteConfig>,
+        options: PoolOptions,
+    ) -> anyhow::Result<Self> {
+        let mut upstreams: Vec<Upstream> = configs
+            .into_iter()
+            .map(Upstream::new)
+            .collect::<anyhow::Result<_>>()?;
+
+        if let Some(policy) = &options.breaker {
+            for upstream in upstreams.iter_mut() {
+                upstream.breaker = Some(CircuitBreaker::new(policy.clone()));
+            }
+        }
+
+        // names label the upstream metrics, so they have to be unique within a chain
+        let names: Vec<String> = upstreams.iter().map(|u| u.name.clone()).collect();
+        for (index, upstream) in upstreams.iter_mut().enumerate() {
+            if names.iter().filter(|name| **name == upstream.name).count() > 1 {
+                upstream.name = format!("{}#{index}", upstream.name);
+            }
+        }
+
+        let mut groups = HashMap::new();
+        for (index, upstream) in upstreams.iter().enumerate() {
+            groups
+                .entry(upstream.group.clone())
+                .or_insert_with(|| UpstreamGroup {
+                    members: vec![],
+                    balancer: Balancer::new(options.policy, upstreams.len()),
+                })
+                .members
+                .push(index);
+        }
+
+        if !groups.contains_key(routing::DEFAULT_GROUP) {
+            anyhow::bail!(
+                "chain `{chain}` needs at least one upstream in the `{}` group",
+                routing::DEFAULT_GROUP
+            );
+        }
+
+        for route in routes.iter() {
+            if !groups.contains_key(&route.group) {
+                anyhow::bail!(
+                    "chain `{chain}` routes to group `{}` which has no upstream",
+                    route.group
+                );
+            }
+        }
+
+        let router = Router::new(routes);
+        if let Some(quorum) = &options.quorum {
+            let serving = router.groups_serving(&quorum.methods);
+            quorum
+                .validate(
+                    serving
+                        .into_iter()
+                        .map(|group| (group, groups[group].members.len())),
+                )
+                .with_context(|| format!("invalid quorum of chain `{chain}`"))?;
+        }
+
+        Ok(Self {
+            chain,
+            upstreams,
+            groups,
+            router,
+            retry: options.retry,
+            timeouts: options.timeouts,
+            hedge: options.hedge,
+            quorum: options.quorum,
+        })
+    }
+
+    pub fn chain(&self) -> &str {
+        &self.chain
+    }
+
+    pub fn upstreams(&self) -> &[Upstream] {
+        &self.upstreams
+    }
+
+    /// Query the chain id of every upstream and make sure they all serve the same chain.
+    /// Unreachable upstreams are tolerated as long as at least one of them answers.
+    pub async fn chain_id(&self, client: &reqwest::Client) -> anyhow::Result<u64> {
+        let mut chain_id = None;
+
+        for upstream in &self.upstreams {
+            match upstream.chain_id(client).await {
+                Ok(id) => match chain_id {
+                    Some(expected) if expected != id => anyhow::bail!(
+                        "upstream `{}` of chain `{}` reports chain id {id}, expected {expected}",
+                        upstream.name,
+                        self.chain,
+                    ),
+                    _ => chain_id = Some(id),
+                },
+                Err(err) => tracing::warn!(
+                    chain = self.chain,
+                    upstream = upstream.name,
+                    "fail to get chain id: {err:#}"
+                ),
+            }
+        }
+
+        chain_id.ok_or_else(|| anyhow::anyhow!("no upstream of `{}` is reachable", self.chain))
+    }
+
+    /// Best head reported by the health checks, zero if unknown.
+    pub fn best_head(&self) -> u64 {
+        self.upstreams.iter().map(|u| u.head()).max().unwrap_or(0)
+    }
+
+    /// Send the requests upstream and return one JSON-RPC response per request. The batch is
+    /// split by upstream group according to the routing rules, and methods subject to the
+    /// quorum are sent apart. The sub-batches are queried concurrently.
+    pub async fn send(
+        &self,
+        client: &reqwest::Client,
+        metrics: &Metrics,
+        requests: &[RpcRequest],
+    ) -> Responses {
+        let head = self.best_head();
+
+        let mut batches: BTreeMap<&str, Vec<&RpcRequest>> = BTreeMap::new();
+        let mut quorum_batches: BTreeMap<&str, Vec<&RpcRequest>> = BTreeMap::new();
+        for request in requests {
+            let group = self.router.group(&request.method, &request.params, head);
+            match &self.quorum {
+                Some(quorum) if quorum.matches(&request.method) => {
+                    quorum_batches.entry(group).or_default().push(request)
+                }
+                _ => batches.entry(group).or_default().push(request),
+            }
+        }
+
+        let (responses, quorum_responses) = futures::join!(
+            future::join_all(batches.into_iter().map(|(group, requests)| {
+                self.send_group(client, metrics, &self.groups[group], requests)
+            })),
+            future::join_all(quorum_batches.into_iter().map(|(group, requests)| {
+                self.send_quorum(client, metrics, &self.groups[group], requests)
+            })),
+        );
+
+        let mut responses = Responses {
+            values: responses.into_iter().flatten().collect(),
+            unconfirmed: HashSet::new(),
+        };
+        for quorum_responses in quorum_responses {
+            responses.values.extend(quorum_responses.values);
+            responses.unconfirmed.extend(quorum_responses.unconfirmed);
+        }
+
+        responses
+    }
+
+    /// Send the requests to `size` upstreams of the group at once and keep the answer enough of
+    /// them agree on. There is no failover nor retry, an unavailable upstream simply does not
+    /// vote.
+    async fn send_quorum(
+        &self,
+        client: &reqwest::Client,
+        metrics: &Metrics,
+        group: &UpstreamGroup,
+        requests: Vec<&RpcRequest>,
+    ) -> Responses {
+        let quorum = self.quorum.as_ref().expect("quorum requests need a quorum");
+
+        let mut order = group.balancer.order(&self.upstreams, &group.members);
+        order.truncate(quorum.size);
+
+        let results = future::join_all(order.iter().map(|&index| {
+            self.upstreams[index].send(
+                client,
+                metrics,
+                &self.chain,
+                &self.timeouts,
+                &requests,
+                true,
+            )
+        }))
+        .await;
+
+        let mut answers: HashMap<RequestId, Vec<Value>> = HashMap::new();
+        for (&index, results) in order.iter().zip(results) {
+            // an upstream answering the same id twice still votes once
+            let mut voted = HashSet::new();
+            for (_, result) in results {
+                match result {
+                    Ok(values) => {
+                        for value in values {
+                            if retry::is_rate_limit_response(&value) {
+                                continue;
+                            }
+                            let Ok(id) = RequestId::try_from(value["id"].clone()) else {
+                                continue;
+                            };
+                            if voted.insert(id.clone()) {
+                                answers.entry(id).or_default().push(value);
+                            }
+                        }
+                    }
+                    Err(err) => tracing::warn!(
+                        chain = self.chain,
+                        upstream = self.upstreams[index].name,
+                        "quorum read failed: {err}"
+                    ),
+                }
+            }
+        }
+
+        let mut responses = Responses {
+            values: Vec::with_capacity(requests.len()),
+            unconfirmed: HashSet::new(),
+        };
+
+        for request in requests {
+            let answers = answers.remove(&request.id).unwrap_or_default();
+            let verdict = quorum.decide(&request.id, answers);
+
+            metrics
+                .upstream_quorum_counter
+                .with_label_values(&[self.chain.as_str(), verdict.label()])
+                .inc();
+
+            match verdict {
+                Verdict::Confirmed(value) => responses.values.push(value),
+                Verdict::Unconfirmed(value) => {
+                    tracing::warn!(
+                        chain = self.chain,
+                        method = request.method,
+                        "upstreams disagree, answering with the most common response"
+                    );
+                    responses.unconfirmed.insert(request.id.clone());
+                    responses.values.push(value);
+                }
+                Verdict::Failed(value) => {
+                    tracing::error!(
+                        chain = self.chain,
+                        method = request.method,
+                        "quorum read failed: {}",
+                        value["error"]["data"]
+                    );
+                    responses.values.push(value);
+                }
+            }
+        }
+
+        responses
+    }
+
+    /// Requests failing with a transient error are retried with backoff according to the retry
+    /// policy, the ones which already succeeded are not sent again. Requests that still fail
+    /// in the end get an error response.
+    async fn send_group(
+        &self,
+        client: &reqwest::Client,
+        metrics: &Metrics,
+        group: &UpstreamGroup,
+        mut pending: Vec<&RpcRequest>,
+    ) -> Vec<Value> {
+        let started = Instant::now();
+        let mut responses = Vec::with_capacity(pending.len());
+        let mut retry = 0;
+
+        loop {
+            let (values, failures) = self
+                .send_once(client, metrics, group, &pending, retry)
+                .await;
+            responses.extend(values);
+
+            let (failures, fatal): (Vec<_>, Vec<_>) =
+                failures.into_iter().partition(|failure| failure.retriable);
+            responses.extend(fatal.into_iter().map(|failure| failure.response));
+
+            if failures.is_empty() {
+                break;
+            }
+
+            retry += 1;
+            let retry_after = failures.iter().filter_map(|f| f.retry_after).max();
+            let delay = self.retry.backoff(retry, retry_after);
+
+            if retry > self.retry.max_retries || started.elapsed() + delay > self.retry.budget {
+                tracing::error!(
+                    chain = self.chain,
+                    "giving up on {} requests after {} retries",
+                    failures.len(),
+                    retry - 1
+                );
+                responses.extend(failures.into_iter().map(|failure| failure.response));
+                break;
+            }
+
+            tracing::warn!(
+                chain = self.chain,
+                retry,
+                "retrying {} requests in {delay:?}",
+                failures.len()
+            );
+            metrics
+                .upstream_retry_counter
+                .with_label_values(&[self.chain.as_str()])
+                .inc_by(failures.len() as u64);
+
+            actix_web::rt::time::sleep(delay).await;
+            pending = failures
+                .into_iter()
+                .map(|failure| failure.request)
+                .collect();
+        }
+
+        responses
+    }
+
+    /// Send the requests to the upstream picked by the balance policy. Requests which could not
+    /// be served because the upstream is unavailable (see [`UpstreamError::should_failover`])
+    /// move on to the next upstream. Retries start from a different upstream than the previous
+    /// attempt.
+    async fn send_once<'a>(
+        &self,
+        client: &reqwest::Client,
+        metrics: &Metrics,
+        group: &UpstreamGroup,
+        requests: &[&'a RpcRequest],
+        retry: u32,
+    ) -> (Vec<Value>, Vec<Failure<'a>>) {
+        let mut responses = Vec::with_capacity(requests.len());
+        let mut failures = vec![];
+        let mut pending: Vec<Failure<'a>> = vec![];
+
+        let mut order = group.balancer.order(&self.upstreams, &group.members);
+        let len = order.len();
+        order.rotate_left(retry as usize % len);
+        let mut order = VecDeque::from(order);
+
+        let mut first = true;
+        while let Some(index) = order.pop_front() {
+            let requests: Vec<&'a RpcRequest> = match first {
+                true => requests.to_vec(),
+                false => pending.drain(..).map(|failure| failure.request).collect(),
+            };
+
+            if requests.is_empty() {
+                break;
+            }
+
+            let hedge = match (&self.hedge, order.front()) {
+                (Some(hedge), Some(&secondary))
+                    if first && requests.iter().all(|r| hedge::is_read_only(&r.method)) =>
+                {
+                    Some((hedge, secondary))
+                }
+                _ => None,
+            };
+            first = false;
+
+            let (index, results) = match hedge {
+                Some((hedge, secondary)) => {
+                    let (winner, results, hedged) = self
+                        .send_hedged(client, metrics, hedge, index, secondary, &requests)
+                        .await;
+                    if hedged {
+                        order.pop_front();
+                    }
+                    (winner, results)
+                }
+                None => {
+                    // the last upstream queues for rate limit capacity, the others let it go
+                    // further
+                    let wait = order.is_empty();
+                    let results = self.upstreams[index]
+                        .send(
+                            client,
+                            metrics,
+                            &self.chain,
+                            &self.timeouts,
+                            &requests,
+                            wait,
+                        )
+                        .await;
+                    (index, results)
+                }
+            };
+
+            let upstream = &self.upstreams[index];
+            for (chunk, result) in results {
+                match result {
+                    Ok(mut values) => {
+                        // the only response to a single request answers it whatever its id
+                        if let ([request], [value]) = (chunk.as_slice(), values.as_mut_slice()) {
+                            value["id"] = json!(request.id);
+                        }
+
+                        let mut unanswered = chunk;
+                        for value in values {
+                            let answered = RequestId::try_from(value["id"].clone())
+                                .ok()
+                                .and_then(|id| unanswered.iter().position(|r| r.id == id));
+                            let Some(answered) = answered else {
+                                tracing::warn!(
+                                    chain = self.chain,
+                                    upstream = upstream.name,
+                                    "rpc response has an unknown id, response is ignored: {value}"
+                                );
+                                continue;
+                            };
+
+                            let request = unanswered.swap_remove(answered);
+                            match retry::is_rate_limit_response(&value) {
+                                true => failures.push(Failure {
+                                    request,
+                                    response: value,
+                                    retriable: true,
+                                    retry_after: None,
+                                }),
+                                false => responses.push(value),
+                            }
+                        }
+
+                        // responses are matched by id only, never by position
+                        let err = UpstreamError::InvalidResponse(json!("missing response"));
+                        failures.extend(unanswered.into_iter().map(|r| Failure::new(r, &err)));
+                    }
+                    Err(err @ (UpstreamError::Throttled | UpstreamError::CircuitOpen)) => {
+                        pending.extend(chunk.into_iter().map(|r| Failure::new(r, &err)));
+                    }
+                    Err(err) if err.should_failover() => {
+                        tracing::warn!(
+                            chain = self.chain,
+                            upstream = upstream.name,
+                            "upstream unavailable, failing over: {err}"
+                        );
+                        pending.extend(chunk.into_iter().map(|r| Failure::new(r, &err)));
+                    }
+                    Err(err) => {
+                        tracing::error!(
+                            chain = self.chain,
+                            upstream = upstream.name,
+                            "fail to make rpc request because: {err}"
+                        );
+                        failures.extend(chunk.into_iter().map(|r| Failure::new(r, &err)));
+                    }
+                }
+            }
+        }
+
+        failures.extend(pending);
+        (responses, failures)
+    }
+
+    /// Send the requests to `primary`, and to `secondary` as well if `primary` is slower than
+    /// its usual latency. The first complete success wins, the other request is dropped.
+    /// Returns the upstream whose results are used, and whether `secondary` was involved.
+    async fn send_hedged<'a>(
+        &self,
+        client: &reqwest::Client,
+        metrics: &Metrics,
+        hedge: &HedgeConfig,
+        primary: usize,
+        secondary: usize,
+        requests: &[&'a RpcRequest],
+    ) -> (usize, ChunkResults<'a>, bool) {
+        let send = |index: usize| {
+            self.upstreams[index].send(
+                client,
+                metrics,
+                &self.chain,
+                &self.timeouts,
+                requests,
+                false,
+            )
+        };
+
+        let delay = hedge.delay(&self.upstreams[primary].latency_samples());
+        let mut first = pin!(send(primary));
+        let timer = pin!(actix_web::rt::time::sleep(delay));
+
+        if let Either::Left((results, _)) = future::select(first.as_mut(), timer).await {
+            return (primary, results, false);
+        }
+
+        tracing::debug!(
+            chain = self.chain,
+            upstream = self.upstreams[primary].name,
+            "no response after {delay:?}, hedging to {}",
+            self.upstreams[secondary].name
+        );
+
+        let second = pin!(send(secondary));
+        let is_success = |results: &ChunkResults| results.iter().all(|(_, r)| r.is_ok());
+
+        let (winner, results) = match future::select(first, second).await {
+            Either::Left((results, _)) if is_success(&results) => (primary, results),
+            Either::Left((_, second)) => (secondary, second.await),
+            Either::Right((results, _)) if is_success(&results) => (secondary, results),
+            Either::Right((_, first)) => (primary, first.await),
+        };
+
+        let label = if winner == primary {
+            "primary"
+        } else {
+            "hedge"
+        };
+        metrics
+            .upstream_hedge_counter
+            .with_label_values(&[self.chain.as_str(), label])
+            .inc();
+
+        (winner, results, true)
+    }
+}
+
+/// Outcome of each chunk of requests sent to an upstream.
+type ChunkResults<'a> = Vec<(Vec<&'a RpcRequest>, Result<Vec<Value>, UpstreamError>)>;
+
+/// A request that could not be served, with the response to return if it is not retried.
+struct Failure<'a> {
+    request: &'a RpcRequest,
+    response: Value,
+    retriable: bool,
+    retry_after: Option<Duration>,
+}
+
+impl<'a> Failure<'a> {
+    fn new(request: &'a RpcRequest, err: &UpstreamError) -> Self {
+        Self {
+            request,
+            response: err.to_response(&request.id),
+            retriable: err.is_retriable(),
+            retry_after: err.retry_after(),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum UpstreamError {
+    Transport(reqwest::Error),
+    Status {
+        status: StatusCode,
+        retry_after: Option<Duration>,
+    },
+    /// The upstream answered a batch with something else than an array, or a single request
+    /// with something else than an object.
+    InvalidResponse(Value),
+    /// The outbound rate limit of the upstream is exhausted.
+    Throttled,
+    /// The circuit breaker of the upstream is open.
+    CircuitOpen,
+    /// Connection failure of a persistent socket.
+    Socket(String),
+    /// No response over a persistent socket in time.
+    Timeout,
+}
+
+impl UpstreamError {
+    /// Whether the error means the upstream is unable to serve us right now, so that the
+    /// same request is worth sending to another upstream.
+    pub fn should_failover(&self) -> bool {
+        match self {
+            UpstreamError::Transport(err) => {
+                err.is_connect() || err.is_timeout() || err.is_request() || err.is_body()
+            }
+            UpstreamError::Status { status, .. } => {
+                status.is_server_error() || *status == StatusCode::TOO_MANY_REQUESTS
+            }
+            UpstreamError::InvalidResponse(_) => false,
+            UpstreamError::Throttled
+            | UpstreamError::CircuitOpen
+            | UpstreamError::Socket(_)
+            | UpstreamError::Timeout => true,
+        }
+    }
+
+    /// Whether the error is transient, so that the same request is worth retrying later.
+    pub fn is_retriable(&self) -> bool {
+        match self {
+            UpstreamError::Transport(err) => {
+                err.is_connect() || err.is_timeout() || err.is_request() || err.is_body()
+            }
+            UpstreamError::Status { status, .. } => matches!(
+                *status,
+                StatusCode::TOO_MANY_REQUESTS
+                    | StatusCode::BAD_GATEWAY
+                    | StatusCode::SERVICE_UNAVAILABLE
+                    | StatusCode::GATEWAY_TIMEOUT
+            ),
+            UpstreamError::Socket(_) | UpstreamError::Timeout => true,
+            // fails over to the other upstreams, once none is left the request fails fast
+            UpstreamError::InvalidResponse(_)
+            | UpstreamError::Throttled
+            | UpstreamError::CircuitOpen => false,
+        }
+    }
+
+    pub fn retry_after(&self) -> Option<Duration> {
+        match self {
+            UpstreamError::Status { retry_after, .. } => *retry_after,
+            _ => None,
+        }
+    }
+
+    /// Error response returned to the client for a request which could not be served.
+    pub fn to_response(&self, id: &RequestId) -> Value {
+        let data = match self {
+            UpstreamError::InvalidResponse(response) => json!({
+                "error": "invalid rpc response from backend",
+                "reason": "unexpected response shape",
+                "response": response.to_string(),
+            }),
+            err => json!({
+                "error": "fail to make rpc request to backend",
+                "reason": err.to_string(),
+            }),
+        };
+
+        let response =
+            JsonRpcResponse::from_error(Some(id.clone()), DefinedError::InternalError(Some(data)));
+        serde_json::to_value(response).expect("json-rpc response is always serializable")
+    }
+}
+
+impl Display for UpstreamError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            UpstreamError::Transport(err) => write!(f, "{err}"),
+            UpstreamError::Status { status, .. } => write!(f, "upstream responded with {status}"),
+            UpstreamError::InvalidResponse(response) => {
+                write!(f, "invalid rpc response: {response}")
+            }
+            UpstreamError::Throttled => write!(f, "upstream rate limit exceeded"),
+            UpstreamError::CircuitOpen => write!(f, "upstream circuit breaker open"),
+            UpstreamError::Socket(err) => write!(f, "{err}"),
+            UpstreamError::Timeout => write!(f, "operation timed out"),
+        }
+    }
+}
+
+impl std::error::Error for UpstreamError {}
+
+impl From<reqwest::Error> for UpstreamError {
+    fn from(err: reqwest::Error) -> Self {
+        // urls often embed API keys
+        UpstreamError::Transport(err.without_url())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::atomic::AtomicUsize;
+    use std::sync::Arc;
+
+    use super::*;
+    use testing::{answer, fake_upstream};
+
+    fn request(index: usize, method: &str) -> RpcRequest {
+        let id = RequestId::try_from(json!(index)).unwrap();
+        RpcRequest::new_uncachable(index, id, method.to_string(), json!([]))
+    }
+
+    fn status(status: StatusCode) -> UpstreamError {
+        UpstreamError::Status {
+            status,
+            retry_after: None,
+        }
+    }
+
+    #[test]
+    fn test_status_failover() {
+        assert!(status(StatusCode::TOO_MANY_REQUESTS).should_failover());
+        assert!(status(StatusCode::BAD_GATEWAY).should_failover());
+        assert!(status(StatusCode::INTERNAL_SERVER_ERROR).should_failover());
+        assert!(!status(StatusCode::BAD_REQUEST).should_failover());
+        assert!(!status(StatusCode::UNAUTHORIZED).should_failover());
+    }
+
+    #[test]
+    fn test_status_retriable() {
+        assert!(status(StatusCode::TOO_MANY_REQUESTS).is_retriable());
+        assert!(status(StatusCode::BAD_GATEWAY).is_retriable());
+        assert!(status(StatusCode::SERVICE_UNAVAILABLE).is_retriable());
+        assert!(status(StatusCode::GATEWAY_TIMEOUT).is_retriable());
+        assert!(!status(StatusCode::INTERNAL_SERVER_ERROR).is_retriable());
+        assert!(!status(StatusCode::BAD_REQUEST).is_retriable());
+    }
+
+    #[test]
+    fn test_default_name() {
+        let upstream = Upstream::new(UpstreamConfig::new(
+            Url::parse("https://rpc.ankr.com/eth/secret").unwrap(),
+        ))
+        .unwrap();
+        assert_eq!(upstream.name, "rpc.ankr.com");
+
+        let upstream = Upstream::new(UpstreamConfig::new(
+            Url::parse("http://127.0.0.1:8545").unwrap(),
+        ))
+        .unwrap();
+        assert_eq!(upstream.name, "127.0.0.1:8545");
+    }
+
+    fn options(quorum: Option<QuorumConfig>) -> PoolOptions {
+        PoolOptions {
+            policy: BalancePolicy::Failover,
+            retry: RetryPolicy::default(),
+            timeouts: Timeouts::new(Duration::from_secs(30), Default::default()),
+            hedge: None,
+            quorum,
+            breaker: None,
+        }
+    }
+
+    #[test]
+    fn test_quorum_group_size() {
+        let upstream = |url: &str, group: &str| {
+            let mut config = UpstreamConfig::new(Url::parse(url).unwrap());
+            config.group = group.to_string();
+            config
+        };
+        let upstreams = || {
+            vec![
+                upstream("https://rpc.ankr.com/eth", "default"),
+                upstream("https://eth.llamarpc.com", "default"),
+                upstream("https://archive.example.com", "archive"),
+            ]
+        };
+        let quorum: QuorumConfig = serde_json::from_value(json!({"size": 2})).unwrap();
+        let route = |methods: &[&str]| RouteConfig {
+            methods: methods.iter().map(|m| m.to_string()).collect(),
+            group: "archive".to_string(),
+            min_block_depth: None,
+        };
+
+        let pool = UpstreamPool::new(
+            "ETH".to_string(),
+            upstreams(),
+            vec![route(&["debug_*"])],
+            options(Some(quorum.clone())),
+        );
+        assert!(pool.is_ok());
+
+        // a single archive upstream can't confirm the logs routed to it
+        let pool = UpstreamPool::new(
+            "ETH".to_string(),
+            upstreams(),
+            vec![route(&["eth_getLogs"])],
+            options(Some(quorum)),
+        );
+        assert!(pool.is_err());
+    }
+
+    type Received = Arc<Mutex<Vec<Value>>>;
+
+    /// An upstream answering each request with its method, along with the request bodies it got.
+    fn recording_upstream(batch: bool, max_batch_size: Option<usize>) -> (Upstream, Received) {
+        let received = Received::default();
+        let mut config = {
+            let received = received.clone();
+            let answer = answer(|request| request["method"].clone());
+            fake_upstream(move |body| {
+                received.lock().unwrap().push(body.clone());
+                answer(body)
+            })
+        };
+        config.batch = batch;
+        config.max_batch_size = max_batch_size;
+        (Upstream::new(config).unwrap(), received)
+    }
+
+    async fn send_to(upstream: &Upstream, requests: &[RpcRequest]) -> Vec<Result<usize, String>> {
+        let timeouts = Timeouts::new(Duration::from_secs(5), Default::default());
+        let requests = requests.iter().collect::<Vec<_>>();
+        let client = reqwest::Client::new();
+        let metrics = Metrics::new("test");
+
+        let results = upstream
+            .send(&client, &metrics, "ETH", &timeouts, &requests, true)
+            .await;
+        results
+            .into_iter()
+            .map(|(chunk, result)| match result {
+                Ok(values) if values.len() == chunk.len() => Ok(values.len()),
+                Ok(values) => Err(format!(
+                    "{} responses to {} requests",
+                    values.len(),
+                    chunk.len()
+                )),
+                Err(err) => Err(err.to_string()),
+            })
+            .collect()
+    }
+
+    #[actix_web::test]
+    async fn test_max_batch_size() {
+        let (upstream, received) = recording_upstream(true, Some(2));
+        let requests = (0..5)
+            .map(|index| request(index, "eth_chainId"))
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            send_to(&upstream, &requests).await,
+            vec![Ok(2), Ok(2), Ok(1)]
+        );
+
+        let mut sizes = received
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|body| body.as_array().unwrap().len())
+            .collect::<Vec<_>>();
+        sizes.sort_unstable();
+        assert_eq!(sizes, vec![1, 2, 2]);
+    }
+
+    #[actix_web::test]
+    async fn test_unbatched() {
+        let (upstream, received) = recording_upstream(false, None);
+        let requests = (0..3)
+            .map(|index| request(index, "eth_chainId"))
+            .collect::<Vec<_>>();
+
+        assert_eq!(send_to(&upstream, &requests).await, vec![Ok(1); 3]);
+
+        // a single request each time, never a batch
+        let received = received.lock().unwrap();
+        assert_eq!(received.len(), 3);
+        assert!(received.iter().all(|body| body["method"] == "eth_chainId"));
+    }
+
+    #[actix_web::test]
+    async fn test_invalid_response_shape() {
+        let unwrap_batch = |body: Value| match body {
+            Value::Array(mut requests) => {
+                json!({"jsonrpc": "2.0", "id": requests.remove(0)["id"], "result": "0x1"})
+            }
+            request => json!([{"jsonrpc": "2.0", "id": request["id"], "result": "0x1"}]),
+        };
+        let requests = vec![request(0, "eth_chainId")];
+
+        // an object for a batch
+        let upstream = Upstream::new(fake_upstream(unwrap_batch)).unwrap();
+        let results = send_to(&upstream, &requests).await;
+        assert!(matches!(&results[..], [Err(err)] if err.starts_with("invalid rpc response")));
+
+        // an array for a single request
+        let mut config = fake_upstream(unwrap_batch);
+        config.batch = false;
+        let upstream = Upstream::new(config).unwrap();
+        let results = send_to(&upstream, &requests).await;
+        assert!(matches!(&results[..], [Err(err)] if err.starts_with("invalid rpc response")));
+    }
+
+    #[actix_web::test]
+    async fn test_breaker_once_per_call() {
+        let hits = Arc::new(AtomicUsize::new(0));
+        let mut config = {
+            let hits = hits.clone();
+            fake_upstream(move |_| {
+                hits.fetch_add(1, Ordering::Relaxed);
+                Value::Null
+            })
+        };
+        config.max_batch_size = Some(1);
+
+        let mut options = options(None);
+        options.retry.max_retries = 3;
+        options.retry.base_delay = Duration::from_secs(1);
+        options.breaker = Some(BreakerPolicy {
+            threshold: 2,
+            window: Duration::from_secs(60),
+            open_duration: Duration::from_secs(60),
+        });
+        let pool = UpstreamPool::new("ETH".to_string(), vec![config], vec![], options).unwrap();
+
+        let client = reqwest::Client::new();
+        let metrics = Metrics::new("test");
+        let requests = (0..3)
+            .map(|index| request(index, "eth_chainId"))
+            .collect::<Vec<_>>();
+
+        // a single failure for the three chunks of the call
+        pool.send(&client, &metrics, &requests).await;
+        assert_eq!(hits.load(Ordering::Relaxed), 3);
+        pool.send(&client, &metrics, &requests).await;
+        assert_eq!(hits.load(Ordering::Relaxed), 6);
+
+        // no upstream is left, the requests fail without waiting for a retry
+        let started = Instant::now();
+        let responses = pool.send(&client, &metrics, &requests).await;
+        assert!(started.elapsed() < Duration::from_millis(500));
+        assert_eq!(hits.load(Ordering::Relaxed), 6);
+        assert!(responses
+            .values
+            .iter()
+            .all(|value| !value["error"].is_null()));
+    }
+
+    #[actix_web::test]
+    async fn test_quorum_vote_once() {
+        // answers every request twice, with a wrong result
+        let duplicating = fake_upstream(|body| {
+            let responses =
+                body.as_array().unwrap().iter().map(
+                    |request| json!({"jsonrpc": "2.0", "id": request["id"], "result": "0xbad"}),
+                );
+            responses
+                .flat_map(|response| [response.clone(), response])
+                .collect()
+        });
+        let honest = fake_upstream(answer(|_| json!("0x1")));
+
+        let quorum: QuorumConfig = serde_json::from_value(json!({"size": 2})).unwrap();
+        let pool = UpstreamPool::new(
+            "ETH".to_string(),
+            vec![duplicating, honest],
+            vec![],
+            options(Some(quorum)),
+        )
+        .unwrap();
+
+        let client = reqwest::Client::new();
+        let requests = vec![request(0, "eth_getLogs")];
+        let responses = pool.send(&client, &Metrics::new("test"), &requests).await;
+
+        assert_eq!(responses.values.len(), 1);
+        assert!(responses.values[0]["result"].is_null());
+        assert!(!responses.values[0]["error"].is_null());
+    }
+
+    #[test]
+    fn test_unique_names() {
+        let pool = UpstreamPool::new(
+            "ETH".to_string(),
+            vec![
+                UpstreamConfig::new(Url::parse("https://rpc.ankr.com/eth/key1").unwrap()),
+                UpstreamConfig::new(Url::parse("https://rpc.ankr.com/eth/key2").unwrap()),
+                UpstreamConfig::new(Url::parse("https://eth.llamarpc.com").unwrap()),
+            ],
+            vec![],
+            options(None),
+        )
+        .unwrap();
+
+        let names: Vec<&str> = pool.upstreams().iter().map(|u| u.name.as_str()).collect();
+        assert_eq!(
+            names,
+            vec!["rpc.ankr.com#0", "rpc.ankr.com#1", "eth.llamarpc.com"]
+        );
+    }
+}
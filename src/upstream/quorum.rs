@@ -0,0 +1,223 @@
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+use super::routing::method_matches;
+use crate::json_rpc::{DefinedError, JsonRpcResponse, RequestId};
+
+/// Send some methods to several upstreams and only trust the answer when enough of them agree.
+#[derive(Deserialize, Clone, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct QuorumConfig {
+    /// Methods read from several upstreams, patterns match as in routes.
+    #[serde(default = "default_methods")]
+    pub methods: Vec<String>,
+
+    /// Number of upstreams each request is sent to.
+    #[serde(default = "default_size")]
+    pub size: usize,
+
+    /// Number of identical answers needed to confirm a result.
+    #[serde(default = "default_min_agree")]
+    pub min_agree: usize,
+
+    #[serde(default)]
+    pub on_disagreement: Disagreement,
+}
+
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum Disagreement {
+    /// Answer with a JSON-RPC error.
+    #[default]
+    Error,
+    /// Answer with the most common response, without caching it.
+    Majority,
+}
+
+fn default_methods() -> Vec<String> {
+    vec![
+        "eth_getTransactionReceipt".to_string(),
+        "eth_getBlockByNumber".to_string(),
+        "eth_getLogs".to_string(),
+    ]
+}
+
+fn default_size() -> usize {
+    3
+}
+
+fn default_min_agree() -> usize {
+    2
+}
+
+/// Outcome of a quorum read for one request.
+#[derive(Debug, PartialEq)]
+pub enum Verdict {
+    /// Enough upstreams agree on this response.
+    Confirmed(Value),
+    /// The most common response, not confirmed by enough upstreams.
+    Unconfirmed(Value),
+    /// Error response sent back to the client.
+    Failed(Value),
+}
+
+impl Verdict {
+    pub fn label(&self) -> &'static str {
+        match self {
+            Verdict::Confirmed(_) => "confirmed",
+            Verdict::Unconfirmed(_) => "majority",
+            Verdict::Failed(_) => "failed",
+        }
+    }
+}
+
+impl QuorumConfig {
+    /// `groups` are the upstream groups serving the quorum methods, with their number of
+    /// upstreams. A group with fewer upstreams than `min_agree` could never confirm a result.
+    pub fn validate<'a>(
+        &self,
+        groups: impl IntoIterator<Item = (&'a str, usize)>,
+    ) -> anyhow::Result<()> {
+        if self.min_agree == 0 || self.min_agree > self.size {
+            anyhow::bail!(
+                "quorum needs 0 < min_agree <= size, got min_agree {} and size {}",
+                self.min_agree,
+                self.size
+            );
+        }
+
+        for (group, upstreams) in groups {
+            if upstreams < self.min_agree {
+                anyhow::bail!(
+                    "group `{group}` serves quorum methods with {upstreams} upstreams, fewer than \
+                     min_agree {}",
+                    self.min_agree
+                );
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn matches(&self, method: &str) -> bool {
+        self.methods
+            .iter()
+            .any(|pattern| method_matches(pattern, method))
+    }
+
+    /// Compare the responses of the upstreams for the same request. Responses are equal when
+    /// their result, or error, is. `responses` comes in upstream order, which breaks ties.
+    pub fn decide(&self, id: &RequestId, responses: Vec<Value>) -> Verdict {
+        let mut votes: Vec<(Value, usize)> = vec![];
+        for response in responses {
+            match votes
+                .iter_mut()
+                .find(|(vote, _)| same_answer(vote, &response))
+            {
+                Some((_, count)) => *count += 1,
+                None => votes.push((response, 1)),
+            }
+        }
+
+        // stable sort, the earliest answer wins ties
+        votes.sort_by(|(_, a), (_, b)| b.cmp(a));
+
+        let Some((response, count)) = votes.into_iter().next() else {
+            return Verdict::Failed(error(
+                id,
+                json!({ "error": "no upstream answered the quorum read" }),
+            ));
+        };
+
+        if count >= self.min_agree {
+            return Verdict::Confirmed(response);
+        }
+
+        match self.on_disagreement {
+            Disagreement::Majority => Verdict::Unconfirmed(response),
+            Disagreement::Error => Verdict::Failed(error(
+                id,
+                json!({
+                    "error": "upstreams disagree",
+                    "agree": count,
+                    "required": self.min_agree,
+                }),
+            )),
+        }
+    }
+}
+
+fn same_answer(a: &Value, b: &Value) -> bool {
+    a.get("result") == b.get("result") && a.get("error") == b.get("error")
+}
+
+fn error(id: &RequestId, data: Value) -> Value {
+    let response =
+        JsonRpcResponse::from_error(Some(id.clone()), DefinedError::InternalError(Some(data)));
+    serde_json::to_value(response).expect("json-rpc response is always serializable")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn config(on_disagreement: Disagreement) -> QuorumConfig {
+        QuorumConfig {
+            methods: default_methods(),
+            size: 3,
+            min_agree: 2,
+            on_disagreement,
+        }
+    }
+
+    fn response(result: &str) -> Value {
+        json!({"jsonrpc": "2.0", "id": 1, "result": result})
+    }
+
+    #[test]
+    fn test_confirmed() {
+        let id = RequestId::try_from(json!(1)).unwrap();
+        let verdict = config(Disagreement::Error).decide(
+            &id,
+            vec![response("0xbad"), response("0x1"), response("0x1")],
+        );
+        assert_eq!(verdict, Verdict::Confirmed(response("0x1")));
+    }
+
+    #[test]
+    fn test_disagreement() {
+        let id = RequestId::try_from(json!(1)).unwrap();
+        let responses = vec![response("0x1"), response("0x2")];
+
+        let verdict = config(Disagreement::Majority).decide(&id, responses.clone());
+        assert_eq!(verdict, Verdict::Unconfirmed(response("0x1")));
+
+        let verdict = config(Disagreement::Error).decide(&id, responses);
+        assert!(
+            matches!(verdict, Verdict::Failed(response) if response["error"]["data"]["agree"] == 1)
+        );
+
+        let verdict = config(Disagreement::Majority).decide(&id, vec![]);
+        assert!(matches!(verdict, Verdict::Failed(_)));
+    }
+
+    #[test]
+    fn test_validate() {
+        let config = config(Disagreement::Error);
+        assert!(config.validate([("default", 3)]).is_ok());
+        assert!(config.validate([("default", 2), ("archive", 3)]).is_ok());
+
+        // too few upstreams to ever agree
+        let err = config
+            .validate([("default", 3), ("archive", 1)])
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "group `archive` serves quorum methods with 1 upstreams, fewer than min_agree 2"
+        );
+
+        let mut config = config;
+        config.min_agree = 4;
+        assert!(config.validate([]).is_err());
+    }
+}
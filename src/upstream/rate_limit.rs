@@ -0,0 +1,167 @@
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use serde::Deserialize;
+
+use super::routing::MethodMap;
+
+/// Outbound quota of an upstream. Without `costs` every request costs one token, so
+/// `per_second` is a number of requests per second. With `costs` it is a budget of provider
+/// "compute units" per second.
+#[derive(Deserialize, Clone, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct RateLimitConfig {
+    pub per_second: f64,
+
+    /// Bucket capacity, defaults to `per_second`.
+    #[serde(default)]
+    pub burst: Option<f64>,
+
+    /// Cost by method name, a trailing `*` matches a prefix.
+    #[serde(default)]
+    pub costs: BTreeMap<String, u32>,
+
+    /// Cost of the methods missing from `costs`.
+    #[serde(default = "default_cost")]
+    pub default_cost: u32,
+
+    /// How long a request may queue for tokens when no other upstream has capacity.
+    #[serde(default = "default_max_wait_ms")]
+    pub max_wait_ms: u64,
+}
+
+fn default_cost() -> u32 {
+    1
+}
+
+fn default_max_wait_ms() -> u64 {
+    1000
+}
+
+/// Token bucket. Tokens can be reserved ahead of time, the balance then goes negative and later
+/// callers wait behind the earlier ones.
+pub struct RateLimiter {
+    rate: f64,
+    burst: f64,
+    costs: MethodMap<u32>,
+    default_cost: u32,
+    max_wait: Duration,
+    bucket: Mutex<Bucket>,
+}
+
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+impl RateLimiter {
+    pub fn new(config: RateLimitConfig) -> anyhow::Result<Self> {
+        let burst = config.burst.unwrap_or(config.per_second);
+        if config.per_second <= 0.0 || burst <= 0.0 {
+            anyhow::bail!("rate limit must be positive");
+        }
+
+        Ok(Self {
+            rate: config.per_second,
+            burst,
+            costs: MethodMap::new(config.costs),
+            default_cost: config.default_cost,
+            max_wait: Duration::from_millis(config.max_wait_ms),
+            bucket: Mutex::new(Bucket {
+                tokens: burst,
+                updated: Instant::now(),
+            }),
+        })
+    }
+
+    pub fn cost<'a>(&self, methods: impl IntoIterator<Item = &'a str>) -> f64 {
+        methods
+            .into_iter()
+            .map(|method| *self.costs.get(method).unwrap_or(&self.default_cost) as f64)
+            .sum()
+    }
+
+    /// Take the tokens if they are available right away.
+    pub fn try_acquire(&self, cost: f64) -> bool {
+        self.reserve(cost, Duration::ZERO).is_some()
+    }
+
+    /// Reserve the tokens and return how long to wait before using them, or `None` without
+    /// reserving anything when that would take longer than the max wait.
+    pub fn acquire(&self, cost: f64) -> Option<Duration> {
+        self.reserve(cost, self.max_wait)
+    }
+
+    fn reserve(&self, cost: f64, max_wait: Duration) -> Option<Duration> {
+        let mut bucket = self.bucket.lock().unwrap();
+
+        let now = Instant::now();
+        let elapsed = now.duration_since(bucket.updated).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * self.rate).min(self.burst);
+        bucket.updated = now;
+
+        // a request costing more than the whole bucket only waits for a full bucket
+        let needed = cost.min(self.burst);
+        let wait = match bucket.tokens >= needed {
+            true => Duration::ZERO,
+            false => Duration::from_secs_f64((needed - bucket.tokens) / self.rate),
+        };
+
+        if wait > max_wait {
+            return None;
+        }
+
+        bucket.tokens -= cost;
+        Some(wait)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn limiter(per_second: f64) -> RateLimiter {
+        RateLimiter::new(RateLimitConfig {
+            per_second,
+            burst: None,
+            costs: BTreeMap::from([("eth_call".to_string(), 26), ("debug_*".to_string(), 300)]),
+            default_cost: 10,
+            max_wait_ms: 1000,
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn test_cost() {
+        let limiter = limiter(100.0);
+        assert_eq!(
+            limiter.cost(["eth_call", "debug_traceCall", "eth_chainId"]),
+            336.0
+        );
+    }
+
+    #[test]
+    fn test_try_acquire() {
+        let limiter = limiter(10.0);
+        assert!(limiter.try_acquire(6.0));
+        assert!(limiter.try_acquire(4.0));
+        assert!(!limiter.try_acquire(1.0));
+    }
+
+    #[test]
+    fn test_acquire() {
+        let limiter = limiter(10.0);
+        assert_eq!(limiter.acquire(10.0), Some(Duration::ZERO));
+
+        let wait = limiter.acquire(5.0).unwrap();
+        assert!(wait > Duration::from_millis(400) && wait <= Duration::from_millis(500));
+
+        // queued behind the previous reservation
+        let wait = limiter.acquire(5.0).unwrap();
+        assert!(wait > Duration::from_millis(900) && wait <= Duration::from_millis(1000));
+
+        assert_eq!(limiter.acquire(5.0), None);
+        assert!(!limiter.try_acquire(1.0));
+    }
+}
@@ -0,0 +1,107 @@
+use std::time::Duration;
+
+use rand::Rng;
+use serde_json::Value;
+
+/// JSON-RPC error codes used by providers to signal rate limiting.
+const RATE_LIMIT_ERROR_CODES: [i64; 4] = [-32005, -32029, -32090, 429];
+
+#[derive(Clone, Debug)]
+pub struct RetryPolicy {
+    /// Maximum number of retries for each incoming request, zero disables retries.
+    pub max_retries: u32,
+    pub base_delay: Duration,
+    pub max_delay: Duration,
+    /// No retry is started once this much time has been spent on the request.
+    pub budget: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_retries: 0,
+            base_delay: Duration::from_millis(100),
+            max_delay: Duration::from_secs(2),
+            budget: Duration::from_secs(10),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Exponential backoff with equal jitter, unless the upstream told us how long to wait.
+    pub fn backoff(&self, retry: u32, retry_after: Option<Duration>) -> Duration {
+        if let Some(retry_after) = retry_after {
+            return retry_after;
+        }
+
+        let exp = self
+            .base_delay
+            .saturating_mul(2u32.saturating_pow(retry.saturating_sub(1)))
+            .min(self.max_delay);
+
+        let half = exp / 2;
+        half + rand::thread_rng().gen_range(Duration::ZERO..=half)
+    }
+}
+
+/// Whether a single JSON-RPC response is a rate limit error worth retrying.
+pub fn is_rate_limit_response(response: &Value) -> bool {
+    response["error"]["code"]
+        .as_i64()
+        .is_some_and(|code| RATE_LIMIT_ERROR_CODES.contains(&code))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde_json::json;
+
+    fn policy() -> RetryPolicy {
+        RetryPolicy {
+            max_retries: 5,
+            base_delay: Duration::from_millis(100),
+            max_delay: Duration::from_millis(1000),
+            budget: Duration::from_secs(10),
+        }
+    }
+
+    #[test]
+    fn test_backoff() {
+        let policy = policy();
+
+        for (retry, max) in [
+            (1, 100),
+            (2, 200),
+            (3, 400),
+            (4, 800),
+            (5, 1000),
+            (30, 1000),
+        ] {
+            let delay = policy.backoff(retry, None);
+            assert!(
+                delay >= Duration::from_millis(max / 2),
+                "{retry}: {delay:?}"
+            );
+            assert!(delay <= Duration::from_millis(max), "{retry}: {delay:?}");
+        }
+    }
+
+    #[test]
+    fn test_retry_after() {
+        let delay = policy().backoff(1, Some(Duration::from_secs(3)));
+        assert_eq!(delay, Duration::from_secs(3));
+    }
+
+    #[test]
+    fn test_rate_limit_response() {
+        let response =
+            json!({"jsonrpc": "2.0", "id": 1, "error": {"code": 429, "message": "rate limited"}});
+        assert!(is_rate_limit_response(&response));
+
+        let response = json!({"jsonrpc": "2.0", "id": 1, "error": {"code": -32000, "message": "execution reverted"}});
+        assert!(!is_rate_limit_response(&response));
+
+        let response = json!({"jsonrpc": "2.0", "id": 1, "result": "0x1"});
+        assert!(!is_rate_limit_response(&response));
+    }
+}
@@ -0,0 +1,298 @@
+use std::collections::BTreeSet;
+use std::str::FromStr;
+
+use alloy_primitives::U64;
+use serde::Deserialize;
+use serde_json::Value;
+
+pub const DEFAULT_GROUP: &str = "default";
+
+/// Sends the methods matching `methods` to the upstreams of `group`.
+#[derive(Deserialize, Clone, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct RouteConfig {
+    /// Method names, a trailing `*` matches any method with the given prefix.
+    pub methods: Vec<String>,
+
+    pub group: String,
+
+    /// Only match requests on a block at least this many blocks behind the head, e.g. to send
+    /// historical `eth_call` to archive nodes. Requests without a concrete block never match.
+    #[serde(default)]
+    pub min_block_depth: Option<u64>,
+}
+
+impl RouteConfig {
+    fn matches(&self, method: &str, params: &Value, head: u64) -> bool {
+        if !self
+            .methods
+            .iter()
+            .any(|pattern| method_matches(pattern, method))
+        {
+            return false;
+        }
+
+        match self.min_block_depth {
+            Some(min_block_depth) => match block_param(method, params) {
+                Some(block) => is_historical(block, head, min_block_depth),
+                None => false,
+            },
+            None => true,
+        }
+    }
+}
+
+/// Method name pattern, a trailing `*` matches any method with the given prefix.
+pub(super) fn method_matches(pattern: &str, method: &str) -> bool {
+    match pattern.strip_suffix('*') {
+        Some(prefix) => method.starts_with(prefix),
+        None => method == pattern,
+    }
+}
+
+/// Whether some method matches both patterns.
+fn patterns_overlap(a: &str, b: &str) -> bool {
+    method_matches(a, b.trim_end_matches('*')) || method_matches(b, a.trim_end_matches('*'))
+}
+
+/// Whether every method matching `pattern` matches `route` as well.
+fn pattern_covers(route: &str, pattern: &str) -> bool {
+    match route.strip_suffix('*') {
+        Some(prefix) => pattern.starts_with(prefix),
+        None => route == pattern,
+    }
+}
+
+/// Values keyed by method name pattern. An exact name wins over a pattern, and a longer pattern
+/// over a shorter one.
+#[derive(Clone, Debug)]
+pub(super) struct MethodMap<T> {
+    entries: Vec<(String, T)>,
+}
+
+impl<T> MethodMap<T> {
+    pub fn new(entries: impl IntoIterator<Item = (String, T)>) -> Self {
+        let mut entries: Vec<(String, T)> = entries.into_iter().collect();
+        entries.sort_by_key(|(pattern, _)| (pattern.ends_with('*'), usize::MAX - pattern.len()));
+        Self { entries }
+    }
+
+    pub fn get(&self, method: &str) -> Option<&T> {
+        self.entries
+            .iter()
+            .find(|(pattern, _)| method_matches(pattern, method))
+            .map(|(_, value)| value)
+    }
+}
+
+pub struct Router {
+    routes: Vec<RouteConfig>,
+}
+
+impl Router {
+    pub fn new(routes: Vec<RouteConfig>) -> Self {
+        Self { routes }
+    }
+
+    /// Upstream group serving the request, the first matching route wins.
+    pub fn group(&self, method: &str, params: &Value, head: u64) -> &str {
+        self.routes
+            .iter()
+            .find(|route| route.matches(method, params, head))
+            .map(|route| route.group.as_str())
+            .unwrap_or(DEFAULT_GROUP)
+    }
+
+    /// Groups which may serve some of the methods matching `methods`.
+    pub fn groups_serving(&self, methods: &[String]) -> BTreeSet<&str> {
+        let mut groups = BTreeSet::new();
+        for pattern in methods {
+            // the default group serves what no unconditional route takes whole
+            let mut covered = false;
+            for route in &self.routes {
+                if route.methods.iter().any(|r| patterns_overlap(r, pattern)) {
+                    groups.insert(route.group.as_str());
+                }
+                if route.min_block_depth.is_none()
+                    && route.methods.iter().any(|r| pattern_covers(r, pattern))
+                {
+                    covered = true;
+                    break;
+                }
+            }
+            if !covered {
+                groups.insert(DEFAULT_GROUP);
+            }
+        }
+        groups
+    }
+}
+
+/// Block parameter of the methods reading state at a given block.
+fn block_param<'a>(method: &str, params: &'a Value) -> Option<&'a Value> {
+    let index = match method {
+        "eth_call"
+        | "eth_getBalance"
+        | "eth_getCode"
+        | "eth_getTransactionCount"
+        | "eth_estimateGas"
+        | "debug_traceCall" => 1,
+        "eth_getStorageAt" | "eth_getProof" => 2,
+        "eth_getBlockByNumber" | "eth_getBlockReceipts" | "debug_traceBlockByNumber" => 0,
+        "eth_getLogs" => return params.get(0).map(|filter| &filter["fromBlock"]),
+        _ => return None,
+    };
+
+    params.get(index)
+}
+
+/// Whether the block is at least `min_block_depth` blocks behind `head`. Blocks identified by
+/// hash and blocks seen while the head is still unknown are assumed to be historical.
+fn is_historical(block: &Value, head: u64, min_block_depth: u64) -> bool {
+    // EIP-1898 block parameter
+    let block = match block {
+        Value::Object(object) => match (object.get("blockNumber"), object.get("blockHash")) {
+            (Some(block_number), _) => block_number,
+            (None, Some(_)) => return true,
+            (None, None) => return false,
+        },
+        block => block,
+    };
+
+    match block.as_str() {
+        Some("earliest") => true,
+        Some(s) if s.len() == 66 => true,
+        Some(s) => match U64::from_str(s) {
+            Ok(_) if head == 0 => true,
+            Ok(block_number) => block_number.as_limbs()[0].saturating_add(min_block_depth) <= head,
+            Err(_) => false,
+        },
+        None => false,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde_json::json;
+
+    fn router() -> Router {
+        Router::new(vec![
+            RouteConfig {
+                methods: vec![
+                    "debug_*".to_string(),
+                    "trace_*".to_string(),
+                    "eth_getProof".to_string(),
+                ],
+                group: "trace".to_string(),
+                min_block_depth: None,
+            },
+            RouteConfig {
+                methods: vec!["eth_call".to_string(), "eth_getLogs".to_string()],
+                group: "archive".to_string(),
+                min_block_depth: Some(128),
+            },
+        ])
+    }
+
+    #[test]
+    fn test_method_patterns() {
+        let router = router();
+
+        assert_eq!(
+            router.group("debug_traceTransaction", &json!([]), 1000),
+            "trace"
+        );
+        assert_eq!(router.group("trace_block", &json!([]), 1000), "trace");
+        assert_eq!(router.group("eth_getProof", &json!([]), 1000), "trace");
+        assert_eq!(
+            router.group("eth_getProofs", &json!([]), 1000),
+            DEFAULT_GROUP
+        );
+        assert_eq!(
+            router.group("eth_blockNumber", &json!([]), 1000),
+            DEFAULT_GROUP
+        );
+    }
+
+    #[test]
+    fn test_groups_serving() {
+        let router = router();
+        let methods = |methods: &[&str]| methods.iter().map(|m| m.to_string()).collect::<Vec<_>>();
+
+        assert_eq!(
+            router.groups_serving(&methods(&["debug_traceTransaction"])),
+            BTreeSet::from(["trace"])
+        );
+        assert_eq!(
+            router.groups_serving(&methods(&["debug_*", "eth_getBlockByNumber"])),
+            BTreeSet::from(["trace", DEFAULT_GROUP])
+        );
+        // historical queries only
+        assert_eq!(
+            router.groups_serving(&methods(&["eth_get*"])),
+            BTreeSet::from(["archive", "trace", DEFAULT_GROUP])
+        );
+        assert_eq!(
+            router.groups_serving(&methods(&["eth_getLogs"])),
+            BTreeSet::from(["archive", DEFAULT_GROUP])
+        );
+    }
+
+    #[test]
+    fn test_historical() {
+        let router = router();
+        let call = json!({"to": "0x6b175474e89094c44da98b954eedeac495271d0f"});
+
+        assert_eq!(
+            router.group("eth_call", &json!([call, "0x10"]), 1000),
+            "archive"
+        );
+        assert_eq!(
+            router.group("eth_call", &json!([call, "0x3e0"]), 1000),
+            DEFAULT_GROUP
+        );
+        assert_eq!(
+            router.group("eth_call", &json!([call, "latest"]), 1000),
+            DEFAULT_GROUP
+        );
+        assert_eq!(
+            router.group("eth_call", &json!([call]), 1000),
+            DEFAULT_GROUP
+        );
+        assert_eq!(
+            router.group("eth_call", &json!([call, "earliest"]), 1000),
+            "archive"
+        );
+        assert_eq!(
+            router.group("eth_call", &json!([call, {"blockNumber": "0x10"}]), 1000),
+            "archive"
+        );
+        assert_eq!(
+            router.group(
+                "eth_call",
+                &json!([
+                    call,
+                    "0x1234567890abcdef1234567890abcdef1234567890abcdef1234567890abcdef"
+                ]),
+                1000
+            ),
+            "archive"
+        );
+
+        // head unknown
+        assert_eq!(
+            router.group("eth_call", &json!([call, "0x3e0"]), 0),
+            "archive"
+        );
+
+        assert_eq!(
+            router.group(
+                "eth_getLogs",
+                &json!([{"fromBlock": "0x10", "toBlock": "0x20"}]),
+                1000
+            ),
+            "archive"
+        );
+    }
+}
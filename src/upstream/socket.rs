@@ -0,0 +1,433 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use futures::{SinkExt, StreamExt};
+use reqwest::header::HeaderMap;
+use reqwest::Url;
+use serde_json::Value;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::UnixStream;
+use tokio::sync::{mpsc, oneshot};
+use tokio_tungstenite::tungstenite::client::IntoClientRequest;
+use tokio_tungstenite::tungstenite::Message;
+
+use super::UpstreamError;
+
+/// Used when the caller does not give a timeout, e.g. for the chain id at startup.
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Responses awaited by their id on the connection.
+type Receivers = Vec<(u64, oneshot::Receiver<Value>)>;
+
+pub enum Endpoint {
+    Ws(Url),
+    /// Unix domain socket of a local node.
+    Ipc(PathBuf),
+}
+
+/// JSON-RPC client multiplexing requests over a persistent connection. Request ids are
+/// remapped to ids unique to the connection, so that concurrent batches from different clients
+/// can share it. The connection is opened on the first request and opened again after it drops.
+pub struct SocketClient {
+    endpoint: Endpoint,
+    next_id: AtomicU64,
+    connection: tokio::sync::Mutex<Option<Arc<Connection>>>,
+}
+
+struct Connection {
+    outgoing: mpsc::UnboundedSender<String>,
+    pending: Mutex<HashMap<u64, oneshot::Sender<Value>>>,
+    closed: AtomicBool,
+}
+
+impl SocketClient {
+    pub fn new(endpoint: Endpoint) -> Self {
+        Self {
+            endpoint,
+            next_id: AtomicU64::new(1),
+            connection: tokio::sync::Mutex::new(None),
+        }
+    }
+
+    /// Send a single request or a batch and wait for all the responses.
+    pub async fn request(
+        &self,
+        headers: HeaderMap,
+        body: Value,
+        timeout: Option<Duration>,
+    ) -> Result<Value, UpstreamError> {
+        let timeout = timeout.unwrap_or(DEFAULT_TIMEOUT);
+        let connection = actix_web::rt::time::timeout(timeout, self.connect(headers))
+            .await
+            .map_err(|_| UpstreamError::Timeout)??;
+
+        let is_batch = body.is_array();
+        let mut requests = match body {
+            Value::Array(requests) => requests,
+            request => vec![request],
+        };
+
+        let (original_ids, receivers) = connection.register(&mut requests, &self.next_id)?;
+
+        let payload = match is_batch {
+            true => Value::Array(requests),
+            false => requests.pop().expect("single request"),
+        };
+
+        if connection.outgoing.send(payload.to_string()).is_err() {
+            connection.forget(receivers.iter().map(|(id, _)| *id));
+            return Err(UpstreamError::Socket("connection closed".to_string()));
+        }
+
+        let ids: Vec<u64> = receivers.iter().map(|(id, _)| *id).collect();
+        let responses = futures::future::join_all(receivers.into_iter().map(|(_, rx)| rx));
+        let responses = match actix_web::rt::time::timeout(timeout, responses).await {
+            Ok(responses) => responses,
+            Err(_) => {
+                connection.forget(ids);
+                return Err(UpstreamError::Timeout);
+            }
+        };
+
+        let mut values = Vec::with_capacity(responses.len());
+        for (response, id) in responses.into_iter().zip(original_ids) {
+            let mut response =
+                response.map_err(|_| UpstreamError::Socket("connection closed".to_string()))?;
+            response["id"] = id;
+            values.push(response);
+        }
+
+        Ok(match is_batch {
+            true => Value::Array(values),
+            false => values.pop().expect("single response"),
+        })
+    }
+
+    async fn connect(&self, headers: HeaderMap) -> Result<Arc<Connection>, UpstreamError> {
+        let mut connection = self.connection.lock().await;
+
+        if let Some(connection) = connection.as_ref() {
+            if !connection.closed.load(Ordering::Relaxed) {
+                return Ok(connection.clone());
+            }
+        }
+
+        let new = match &self.endpoint {
+            Endpoint::Ws(url) => connect_ws(url, headers).await?,
+            Endpoint::Ipc(path) => connect_ipc(path).await?,
+        };
+        *connection = Some(new.clone());
+
+        Ok(new)
+    }
+}
+
+impl Connection {
+    fn new(outgoing: mpsc::UnboundedSender<String>) -> Arc<Self> {
+        Arc::new(Self {
+            outgoing,
+            pending: Mutex::new(HashMap::new()),
+            closed: AtomicBool::new(false),
+        })
+    }
+
+    /// Remap the ids of the requests and wait for their responses. Fails once the connection is
+    /// closed, nothing would answer them.
+    fn register(
+        &self,
+        requests: &mut [Value],
+        next_id: &AtomicU64,
+    ) -> Result<(Vec<Value>, Receivers), UpstreamError> {
+        let mut pending = self.pending.lock().unwrap();
+        if self.closed.load(Ordering::Relaxed) {
+            return Err(UpstreamError::Socket("connection closed".to_string()));
+        }
+
+        let mut original_ids = Vec::with_capacity(requests.len());
+        let mut receivers = Vec::with_capacity(requests.len());
+        for request in requests.iter_mut() {
+            let id = next_id.fetch_add(1, Ordering::Relaxed);
+            original_ids.push(std::mem::replace(&mut request["id"], Value::from(id)));
+
+            let (tx, rx) = oneshot::channel();
+            pending.insert(id, tx);
+            receivers.push((id, rx));
+        }
+
+        Ok((original_ids, receivers))
+    }
+
+    fn dispatch_str(&self, message: &str) {
+        match serde_json::from_str::<Value>(message) {
+            Ok(message) => self.dispatch(message),
+            Err(err) => tracing::warn!("invalid json-rpc message from socket: {err}"),
+        }
+    }
+
+    /// Route a message to the requests waiting for it. Messages without a pending id, such as
+    /// subscription notifications, are dropped.
+    fn dispatch(&self, message: Value) {
+        let responses = match message {
+            Value::Array(responses) => responses,
+            response => vec![response],
+        };
+
+        let mut pending = self.pending.lock().unwrap();
+        for response in responses {
+            if let Some(tx) = response["id"].as_u64().and_then(|id| pending.remove(&id)) {
+                let _ = tx.send(response);
+            }
+        }
+    }
+
+    fn forget(&self, ids: impl IntoIterator<Item = u64>) {
+        let mut pending = self.pending.lock().unwrap();
+        for id in ids {
+            pending.remove(&id);
+        }
+    }
+
+    /// Fail all the pending requests, the next request opens a new connection.
+    fn close(&self) {
+        // under the lock of the pending requests, none can be registered after they are failed
+        let mut pending = self.pending.lock().unwrap();
+        self.closed.store(true, Ordering::Relaxed);
+        pending.clear();
+    }
+}
+
+async fn connect_ws(url: &Url, headers: HeaderMap) -> Result<Arc<Connection>, UpstreamError> {
+    let mut request = url
+        .as_str()
+        .into_client_request()
+        .map_err(|err| UpstreamError::Socket(format!("invalid websocket request: {err}")))?;
+    request.headers_mut().extend(headers);
+
+    let (stream, _) = tokio_tungstenite::connect_async(request)
+        .await
+        .map_err(|err| UpstreamError::Socket(format!("fail to connect websocket: {err}")))?;
+    let (mut sink, mut stream) = stream.split();
+
+    let (tx, mut rx) = mpsc::unbounded_channel::<String>();
+    let connection = Connection::new(tx);
+
+    // the writer stops once the connection is dropped, it must not keep it alive
+    let writer = Arc::downgrade(&connection);
+    actix_web::rt::spawn(async move {
+        while let Some(message) = rx.recv().await {
+            if let Err(err) = sink.send(Message::Text(message)).await {
+                tracing::warn!("websocket write failed: {err}");
+                break;
+            }
+        }
+        if let Some(connection) = writer.upgrade() {
+            connection.close();
+        }
+    });
+
+    let reader = connection.clone();
+    actix_web::rt::spawn(async move {
+        while let Some(message) = stream.next().await {
+            match message {
+                Ok(Message::Text(text)) => reader.dispatch_str(&text),
+                Ok(Message::Binary(bytes)) => reader.dispatch_str(&String::from_utf8_lossy(&bytes)),
+                Ok(Message::Close(_)) => break,
+                Ok(_) => {}
+                Err(err) => {
+                    tracing::warn!("websocket read failed: {err}");
+                    break;
+                }
+            }
+        }
+        reader.close();
+    });
+
+    Ok(connection)
+}
+
+async fn connect_ipc(path: &Path) -> Result<Arc<Connection>, UpstreamError> {
+    let stream = UnixStream::connect(path)
+        .await
+        .map_err(|err| UpstreamError::Socket(format!("fail to connect ipc socket: {err}")))?;
+    let (mut read, mut write) = stream.into_split();
+
+    let (tx, mut rx) = mpsc::unbounded_channel::<String>();
+    let connection = Connection::new(tx);
+
+    let writer = Arc::downgrade(&connection);
+    actix_web::rt::spawn(async move {
+        while let Some(message) = rx.recv().await {
+            if let Err(err) = write.write_all(message.as_bytes()).await {
+                tracing::warn!("ipc write failed: {err}");
+                break;
+            }
+        }
+        if let Some(connection) = writer.upgrade() {
+            connection.close();
+        }
+    });
+
+    // messages are JSON values written back to back, without framing
+    let reader = connection.clone();
+    actix_web::rt::spawn(async move {
+        let mut buffer = Vec::new();
+        let mut chunk = [0u8; 8192];
+
+        loop {
+            match read.read(&mut chunk).await {
+                Ok(0) => break,
+                Ok(n) => buffer.extend_from_slice(&chunk[..n]),
+                Err(err) => {
+                    tracing::warn!("ipc read failed: {err}");
+                    break;
+                }
+            }
+
+            let mut messages = serde_json::Deserializer::from_slice(&buffer).into_iter::<Value>();
+            let mut consumed = 0;
+            let mut invalid = false;
+            while let Some(message) = messages.next() {
+                match message {
+                    Ok(message) => {
+                        reader.dispatch(message);
+                        consumed = messages.byte_offset();
+                    }
+                    Err(err) if err.is_eof() => break,
+                    Err(err) => {
+                        tracing::warn!("invalid json-rpc message from ipc socket: {err}");
+                        invalid = true;
+                        break;
+                    }
+                }
+            }
+
+            if invalid {
+                break;
+            }
+            buffer.drain(..consumed);
+        }
+        reader.close();
+    });
+
+    Ok(connection)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_dispatch() {
+        let (tx, _rx) = mpsc::unbounded_channel();
+        let connection = Connection::new(tx);
+
+        let (tx1, mut rx1) = oneshot::channel();
+        let (tx2, mut rx2) = oneshot::channel();
+        connection.pending.lock().unwrap().insert(1, tx1);
+        connection.pending.lock().unwrap().insert(2, tx2);
+
+        connection.dispatch_str(r#"{"jsonrpc":"2.0","method":"eth_subscription","params":{}}"#);
+        connection.dispatch_str(r#"[{"jsonrpc":"2.0","id":2,"result":"0x2"}]"#);
+
+        assert_eq!(rx2.try_recv().unwrap()["result"], json!("0x2"));
+        assert!(rx1.try_recv().is_err());
+
+        connection.close();
+        assert!(connection.pending.lock().unwrap().is_empty());
+        assert!(rx1.try_recv().is_err());
+    }
+
+    #[test]
+    fn test_register_after_close() {
+        let (tx, _rx) = mpsc::unbounded_channel();
+        let connection = Connection::new(tx);
+        let next_id = AtomicU64::new(1);
+
+        let mut requests = vec![json!({"jsonrpc": "2.0", "id": "a", "method": "eth_chainId"})];
+        let (original_ids, receivers) = connection.register(&mut requests, &next_id).unwrap();
+        assert_eq!(original_ids, vec![json!("a")]);
+        assert_eq!(requests[0]["id"], json!(1));
+        assert_eq!(receivers[0].0, 1);
+
+        // fails fast instead of waiting for its timeout
+        connection.close();
+        assert!(connection.register(&mut requests, &next_id).is_err());
+        assert!(connection.pending.lock().unwrap().is_empty());
+    }
+
+    /// Fake node answering on a unix socket with the method and params of each request, in
+    /// reverse order and split across writes.
+    async fn fake_ipc_server(path: PathBuf) {
+        let listener = tokio::net::UnixListener::bind(&path).unwrap();
+
+        actix_web::rt::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut buffer = Vec::new();
+            let mut chunk = [0u8; 1024];
+
+            loop {
+                let n = stream.read(&mut chunk).await.unwrap();
+                if n == 0 {
+                    break;
+                }
+                buffer.extend_from_slice(&chunk[..n]);
+
+                let Ok(request) = serde_json::from_slice::<Value>(&buffer) else {
+                    continue;
+                };
+                buffer.clear();
+
+                let mut responses: Vec<Value> = request
+                    .as_array()
+                    .unwrap()
+                    .iter()
+                    .map(|r| json!({"jsonrpc": "2.0", "id": r["id"], "result": [r["method"], r["params"]]}))
+                    .collect();
+                responses.reverse();
+
+                let response = serde_json::to_vec(&responses).unwrap();
+                let (head, tail) = response.split_at(response.len() / 2);
+                stream.write_all(head).await.unwrap();
+                stream.flush().await.unwrap();
+                stream.write_all(tail).await.unwrap();
+            }
+        });
+    }
+
+    #[actix_web::test]
+    async fn test_ipc() {
+        let path = std::env::temp_dir().join(format!("cached-eth-rpc-{}.ipc", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        fake_ipc_server(path.clone()).await;
+
+        let client = SocketClient::new(Endpoint::Ipc(path.clone()));
+        let batch = json!([
+            {"jsonrpc": "2.0", "id": "a", "method": "eth_chainId", "params": []},
+            {"jsonrpc": "2.0", "id": 7, "method": "eth_getBalance", "params": ["0x1", "latest"]},
+        ]);
+
+        for _ in 0..2 {
+            let response = client
+                .request(
+                    HeaderMap::new(),
+                    batch.clone(),
+                    Some(Duration::from_secs(5)),
+                )
+                .await
+                .unwrap();
+            let mut response = response.as_array().unwrap().clone();
+            response.sort_by_key(|r| r["id"].is_number());
+
+            assert_eq!(response[0]["id"], json!("a"));
+            assert_eq!(response[0]["result"], json!(["eth_chainId", []]));
+            assert_eq!(response[1]["id"], json!(7));
+            assert_eq!(response[1]["result"][0], json!("eth_getBalance"));
+        }
+
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,48 @@
+use std::sync::Arc;
+
+use actix_web::{web, App, HttpResponse, HttpServer};
+use reqwest::Url;
+use serde_json::{json, Value};
+
+use crate::config::UpstreamConfig;
+
+/// Serves JSON-RPC over HTTP on a local port, answering each request body with `handler`, or
+/// with an internal server error when it returns `null`, and returns the config of an upstream
+/// pointing at it.
+pub fn fake_upstream(handler: impl Fn(Value) -> Value + Send + Sync + 'static) -> UpstreamConfig {
+    let handler = Arc::new(handler);
+    let server = HttpServer::new(move || {
+        let handler = handler.clone();
+        App::new().default_service(web::to(move |body: web::Bytes| {
+            let handler = handler.clone();
+            async move {
+                let body = serde_json::from_slice(&body).expect("json-rpc request");
+                match handler(body) {
+                    Value::Null => HttpResponse::InternalServerError().finish(),
+                    response => HttpResponse::Ok().json(response),
+                }
+            }
+        }))
+    })
+    .workers(1)
+    .bind(("127.0.0.1", 0))
+    .expect("fail to bind fake upstream");
+
+    let port = server.addrs()[0].port();
+    actix_web::rt::spawn(server.run());
+
+    UpstreamConfig::new(Url::parse(&format!("http://127.0.0.1:{port}")).unwrap())
+}
+
+/// Answers each request of a single request or a batch with the result of `result`.
+pub fn answer(
+    result: impl Fn(&Value) -> Value + Send + Sync + 'static,
+) -> impl Fn(Value) -> Value + Send + Sync + 'static {
+    move |body| {
+        let respond = |request: &Value| json!({"jsonrpc": "2.0", "id": request["id"], "result": result(request)});
+        match &body {
+            Value::Array(requests) => requests.iter().map(respond).collect(),
+            request => respond(request),
+        }
+    }
+}
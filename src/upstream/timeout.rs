@@ -0,0 +1,56 @@
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+use super::routing::MethodMap;
+
+/// Total time allowed for an upstream request, depending on the methods it carries.
+#[derive(Clone, Debug)]
+pub struct Timeouts {
+    default: Duration,
+    methods: MethodMap<Duration>,
+}
+
+impl Timeouts {
+    /// `methods` maps method name patterns (a trailing `*` matches a prefix) to a timeout in
+    /// milliseconds. An exact name wins over a pattern, and a longer pattern over a shorter one.
+    pub fn new(default: Duration, methods: BTreeMap<String, u64>) -> Self {
+        let methods = MethodMap::new(
+            methods
+                .into_iter()
+                .map(|(pattern, ms)| (pattern, Duration::from_millis(ms))),
+        );
+
+        Self { default, methods }
+    }
+
+    pub fn get(&self, method: &str) -> Duration {
+        self.methods.get(method).copied().unwrap_or(self.default)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_get() {
+        let timeouts = Timeouts::new(
+            Duration::from_secs(30),
+            BTreeMap::from([
+                ("eth_blockNumber".to_string(), 2_000),
+                ("debug_*".to_string(), 120_000),
+                ("debug_traceCall".to_string(), 10_000),
+                ("debug_trace*".to_string(), 60_000),
+            ]),
+        );
+
+        assert_eq!(timeouts.get("eth_blockNumber"), Duration::from_secs(2));
+        assert_eq!(timeouts.get("debug_traceCall"), Duration::from_secs(10));
+        assert_eq!(
+            timeouts.get("debug_traceBlockByNumber"),
+            Duration::from_secs(60)
+        );
+        assert_eq!(timeouts.get("debug_getRawBlock"), Duration::from_secs(120));
+        assert_eq!(timeouts.get("eth_call"), Duration::from_secs(30));
+    }
+}